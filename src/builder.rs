@@ -0,0 +1,154 @@
+use core::fmt::Debug;
+
+use alloc::vec::Vec;
+
+use crate::{EvictionPolicy, LoopSetting, Queue, QueueItem, QueueItemType, QueueState, Rng};
+
+/// Sets up a [`Queue`] with its items and options in one go
+#[derive(Debug)]
+pub struct QueueBuilder<T, U> {
+    items: Vec<QueueItemType<T, U>>,
+    by_human: bool,
+    addhere: Option<usize>,
+    loop_: LoopSetting,
+    repeat_one: bool,
+    history_limit: Option<usize>,
+    history_dedup: bool,
+    max_len: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    shuffled: bool,
+    seed: Option<u64>,
+}
+
+impl<T, U> Default for QueueBuilder<T, U> {
+    fn default() -> Self {
+        QueueBuilder {
+            items: Vec::new(),
+            by_human: false,
+            addhere: None,
+            loop_: LoopSetting::Off,
+            repeat_one: false,
+            history_limit: None,
+            history_dedup: false,
+            max_len: None,
+            eviction_policy: EvictionPolicy::RejectNew,
+            shuffled: false,
+            seed: None,
+        }
+    }
+}
+
+impl<T, U> QueueBuilder<T, U> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn items(mut self, items: Vec<QueueItemType<T, U>>) -> Self {
+        self.items = items;
+        self
+    }
+
+    /// Whether the items were queued by a person, false by default
+    pub fn by_human(mut self, by_human: bool) -> Self {
+        self.by_human = by_human;
+        self
+    }
+
+    /// Puts the AddHere marker on the item at `index` instead of the last one, an index past the
+    /// end keeps it on the last item
+    pub fn addhere(mut self, index: usize) -> Self {
+        self.addhere = Some(index);
+        self
+    }
+
+    pub fn loop_(mut self, loop_: bool) -> Self {
+        self.loop_ = if loop_ { LoopSetting::Forever } else { LoopSetting::Off };
+        self
+    }
+
+    pub fn loop_setting(mut self, setting: LoopSetting) -> Self {
+        self.loop_ = setting;
+        self
+    }
+
+    pub fn repeat_one(mut self, repeat: bool) -> Self {
+        self.repeat_one = repeat;
+        self
+    }
+
+    pub fn history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = Some(limit);
+        self
+    }
+
+    /// See [`Queue::set_history_dedup`]
+    pub fn history_dedup(mut self, dedup: bool) -> Self {
+        self.history_dedup = dedup;
+        self
+    }
+
+    /// See [`Queue::set_max_len`], the items given here are all kept even past it
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    pub fn shuffled(mut self, shuffled: bool) -> Self {
+        self.shuffled = shuffled;
+        self
+    }
+
+    /// Seeds the generator used for shuffling, making shuffles reproducible
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+impl<
+    T: Debug + Clone + PartialEq,
+    U: Debug + PartialEq + Clone + IntoIterator,
+> QueueBuilder<T, U> {
+    /// Makes the queue, with the first item marked `First` and the last one, or the one given to
+    /// [`QueueBuilder::addhere`], marked `AddHere`
+    pub fn build(self) -> Queue<T, U> {
+        let mut queue = Queue::new(false, None);
+        queue.loop_ = self.loop_;
+        if let LoopSetting::Times(n) = self.loop_ {
+            queue.loop_times = n;
+        }
+        queue.repeat_one = self.repeat_one;
+        queue.history_limit = self.history_limit;
+        queue.history_dedup = self.history_dedup;
+        queue.max_len = self.max_len;
+        queue.eviction_policy = self.eviction_policy;
+        if let Some(seed) = self.seed {
+            queue.rng = Rng::new(seed);
+        }
+
+        let by_human = self.by_human;
+        queue.items = self
+            .items
+            .into_iter()
+            .map(|item| QueueItem::from_item_type(item, by_human))
+            .collect();
+        if let Some(first) = queue.items.first_mut() {
+            first.state = QueueState::First;
+        }
+        let len = queue.items.len();
+        let addhere = self.addhere.filter(|&index| index < len).or(len.checked_sub(1));
+        if let Some(index) = addhere {
+            queue.items[index].state = QueueState::AddHere;
+        }
+
+        if self.shuffled {
+            queue.enable_shuffle();
+        }
+        queue
+    }
+}
@@ -0,0 +1,97 @@
+use core::fmt::Debug;
+
+use alloc::vec::Vec;
+
+use crate::{LoopSetting, Queue, QueueError, QueueItemType, QueuePosition};
+
+/// A change to a [`Queue`] as a value, so it can be logged or sent elsewhere and replayed with
+/// [`Queue::apply`].
+///
+/// Two queues that start out the same, shuffle generator included, end up the same after
+/// applying the same commands
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum QueueCommand<T, U> {
+    Add { item: T, by_human: bool },
+    AddNext(T),
+    AddMulti { items: Vec<QueueItemType<T, U>>, by_human: bool },
+    AddMultiNext(Vec<QueueItemType<T, U>>),
+    AddAt { item: QueueItemType<T, U>, position: QueuePosition, by_human: bool },
+    Insert { index: usize, item: QueueItemType<T, U>, by_human: bool, addhere: bool },
+    Remove(usize),
+    Move { from: usize, to: usize },
+    Swap(usize, usize),
+    Next,
+    Skip,
+    Prev,
+    MoveTo(usize),
+    Clear,
+    ClearUpcoming,
+    ClearPlayed,
+    ClearAll,
+    EnableShuffle,
+    DisableShuffle,
+    Unshuffle,
+    SetLoop(LoopSetting),
+    SetRepeatOne(bool),
+}
+
+impl<
+    T: Debug + Clone + PartialEq,
+    U: Debug + PartialEq + Clone + IntoIterator,
+> Queue<T, U> {
+    /// Runs `command` with the method of the same name.
+    ///
+    /// Moves and swaps that are out of bounds fail with [`QueueError::OutOfBounds`] instead of
+    /// panicking
+    pub fn apply(&mut self, command: QueueCommand<T, U>) -> Result<(), QueueError> {
+        use QueueCommand::*;
+
+        match command {
+            Add { item, by_human } => return self.add_item(item, by_human),
+            AddNext(item) => return self.add_item_next(item),
+            AddMulti { items, by_human } => return self.add_multi(items, by_human),
+            AddMultiNext(items) => return self.add_multi_next(items),
+            AddAt { item, position, by_human } => return self.add_at(item, position, by_human),
+            Insert { index, item, by_human, addhere } => {
+                return self.insert(index, item, by_human, addhere);
+            }
+            Remove(index) => return self.remove_item(index).map(|_| ()),
+            Move { from, to } => {
+                self.check_index(from.max(to))?;
+                self.move_item(from, to);
+            }
+            Swap(a, b) => {
+                self.check_index(a.max(b))?;
+                self.swap(a, b);
+            }
+            Next => return self.next().map(|_| ()),
+            Skip => return self.skip().map(|_| ()),
+            Prev => return self.prev().map(|_| ()),
+            MoveTo(index) => return self.move_to(index),
+            Clear => self.clear(),
+            ClearUpcoming => {
+                self.clear_upcoming();
+            }
+            ClearPlayed => self.clear_played(),
+            ClearAll => self.clear_all(),
+            EnableShuffle => self.enable_shuffle(),
+            DisableShuffle => self.disable_shuffle(),
+            Unshuffle => self.unshuffle(),
+            SetLoop(setting) => self.set_loop(setting),
+            SetRepeatOne(repeat) => self.set_repeat_one(repeat),
+        }
+        Ok(())
+    }
+
+    fn check_index(&self, index: usize) -> Result<(), QueueError> {
+        if index < self.items.len() {
+            Ok(())
+        } else {
+            Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            })
+        }
+    }
+}
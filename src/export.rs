@@ -0,0 +1,74 @@
+use core::fmt::Write;
+use core::time::Duration;
+
+use alloc::string::String;
+
+use crate::{Queue, QueueItem, QueueItemType};
+
+/// Something that can be written out as a playlist entry, used by [`Queue::export_m3u`]
+pub trait ExportEntry {
+    /// The path or URI the entry points to
+    fn location(&self) -> String;
+
+    fn title(&self) -> Option<String> {
+        None
+    }
+
+    fn duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl<T, U> Queue<T, U> {
+    /// Writes one line per item with `f`, starting with the played items if `include_history`
+    pub fn export_with<F: FnMut(&QueueItem<T, U>) -> String>(
+        &self,
+        include_history: bool,
+        mut f: F,
+    ) -> String {
+        let history = if include_history { &self.played[..] } else { &[] };
+        let mut out = String::new();
+        for item in history.iter().chain(&self.items) {
+            out.push_str(&f(item));
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl<T: ExportEntry, U: Clone + IntoIterator> Queue<T, U>
+where
+    U::Item: ExportEntry,
+{
+    /// Writes the queue as an extended M3U playlist, with a line for every track of a Multi.
+    ///
+    /// The played items come first if `include_history` is set
+    pub fn export_m3u(&self, include_history: bool) -> String {
+        let history = if include_history { &self.played[..] } else { &[] };
+        let mut out = String::from("#EXTM3U\n");
+        for item in history.iter().chain(&self.items) {
+            match &item.item {
+                QueueItemType::Single(track) => write_m3u_entry(&mut out, track),
+                QueueItemType::Multi(tracks) => {
+                    for track in tracks.clone() {
+                        write_m3u_entry(&mut out, &track);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn write_m3u_entry<E: ExportEntry>(out: &mut String, entry: &E) {
+    let title = entry.title();
+    let duration = entry.duration();
+    if title.is_some() || duration.is_some() {
+        // -1 is how M3U says the length isn't known
+        let seconds = duration.map_or(-1, |duration| duration.as_secs() as i64);
+        // writing to a String can't fail
+        let _ = writeln!(out, "#EXTINF:{seconds},{}", title.unwrap_or_default());
+    }
+    out.push_str(&entry.location());
+    out.push('\n');
+}
@@ -1,8 +1,47 @@
-use std::fmt::Debug;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use core::fmt::{self, Debug};
+use core::mem;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+mod builder;
+mod command;
+mod export;
+mod meta;
+#[cfg(feature = "async")]
+mod notify;
+mod rng;
+#[cfg(feature = "std")]
+mod shared;
+#[cfg(test)]
+mod tests;
+mod transaction;
+
+pub use builder::QueueBuilder;
+pub use command::QueueCommand;
+pub use export::ExportEntry;
+pub use meta::ItemMeta;
+
+use rng::Rng;
+use transaction::Savepoint;
+#[cfg(feature = "async")]
+pub use notify::{QueueSubscriber, Recv};
+#[cfg(feature = "std")]
+pub use shared::{QueueEvent, SharedQueue, SyncQueue};
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum QueueState {
+    /// In `played`, having been played through
     Played,
+    /// In `played`, but was skipped instead of played through
+    Skipped,
     First,
     AddHere,
     NoState,
@@ -11,27 +50,55 @@ pub enum QueueState {
 #[derive(Debug, Clone, PartialEq)]
 #[non_exhaustive]
 pub struct QueueItem<
-    T: Debug + Clone + PartialEq, // T: The Singular Item Type
-    U: Debug + PartialEq + Clone + IntoIterator, // U: an Iterator
+    T, // T: The Singular Item Type
+    U, // U: an Iterator
 > {
     pub item: QueueItemType<T, U>,
     pub state: QueueState,
     pub by_human: bool,
+    /// Which track of a Multi item is playing, always 0 for a Single
+    pub track: usize,
+    /// App data that goes along with the item, see [`QueueItem::with_meta`]
+    pub meta: ItemMeta,
+    /// Whether the item can't be played, see [`Queue::set_item_error`]
+    pub errored: bool,
+    /// Higher goes first in [`Queue::sort_by_priority`], 0 by default
+    pub priority: i32,
+    /// When the item went into `played`, `None` for upcoming items
+    #[cfg(feature = "std")]
+    pub played_at: Option<SystemTime>,
+    pub(crate) tag: ItemTag,
+}
+
+/// What the queue keeps track of about an item for itself, left out when comparing items
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ItemTag {
+    /// Whether the item went into `played` by being played or skipped since the queue last
+    /// started over, which makes it part of the cycle a looping queue plays again
+    cycle: bool,
+    /// Which value the item has within the outermost open transaction, 0 for one it hasn't seen
+    key: u64,
+}
+
+impl PartialEq for ItemTag {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 #[non_exhaustive]
 pub enum QueueItemType<
-    T: Debug + Clone + PartialEq, // T: The Singular Item Type
-    U: Debug + PartialEq + Clone + IntoIterator, // U: The Multi-Item Type. Needs to be tracked as multiple items
+    T, // T: The Singular Item Type
+    U, // U: The Multi-Item Type. Needs to be tracked as multiple items
 > {
     Single(T),
     Multi(U)
 }
 
 impl<
-    T: Debug + Clone + PartialEq, // T: The Singular Item Type
-    U: Debug + PartialEq + Clone + IntoIterator, // U: The Multi-Item Type. Needs to be tracked as multiple items
+    T, // T: The Singular Item Type
+    U, // U: The Multi-Item Type. Needs to be tracked as multiple items
 > QueueItemType<T, U>  {
     pub fn from_single(item: T) -> Self {
         QueueItemType::Single(item)
@@ -42,397 +109,2880 @@ impl<
     }
 }
 
+impl<T, U: Clone + IntoIterator> QueueItemType<T, U> {
+    /// The number of tracks this stands for, counting every item of a Multi
+    pub(crate) fn track_count(&self) -> usize {
+        match self {
+            QueueItemType::Single(_) => 1,
+            QueueItemType::Multi(items) => items.clone().into_iter().count(),
+        }
+    }
 
-impl<
-    T: Debug + Clone + PartialEq,
-    U: Debug + PartialEq + Clone + IntoIterator,
->
-QueueItem<T, U> {
-    pub fn from_item_type(item: QueueItemType<T, U>) -> Self {
+    /// Whether this is `single`, or a Multi with `single` in it
+    pub(crate) fn contains_single(&self, single: &T) -> bool
+    where
+        T: PartialEq,
+        U: IntoIterator<Item = T>,
+    {
+        match self {
+            QueueItemType::Single(item) => item == single,
+            QueueItemType::Multi(items) => items.clone().into_iter().any(|item| item == *single),
+        }
+    }
+}
+
+
+impl<T, U> QueueItem<T, U> {
+    pub fn from_item_type(item: QueueItemType<T, U>, by_human: bool) -> Self {
         QueueItem {
             item,
             state: QueueState::NoState,
-            by_human: false,
+            by_human,
+            track: 0,
+            meta: ItemMeta::default(),
+            errored: false,
+            priority: 0,
+            #[cfg(feature = "std")]
+            played_at: None,
+            tag: ItemTag::default(),
+        }
+    }
+
+    pub fn by_human(mut self, by_human: bool) -> Self {
+        self.by_human = by_human;
+        self
+    }
+
+    pub fn with_state(mut self, state: QueueState) -> Self {
+        self.state = state;
+        self
+    }
+
+    pub fn item(&self) -> &QueueItemType<T, U> {
+        &self.item
+    }
+
+    pub fn state(&self) -> QueueState {
+        self.state
+    }
+
+    pub fn is_by_human(&self) -> bool {
+        self.by_human
+    }
+
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Orders items by priority, highest first, the order [`Queue::sort_by_priority`] uses
+    pub fn cmp_priority(&self, other: &Self) -> Ordering {
+        other.priority.cmp(&self.priority)
+    }
+
+    /// Gets an item out of `played` ready to be queued again
+    pub(crate) fn requeue(&mut self) {
+        self.state = QueueState::NoState;
+        self.track = 0;
+        self.tag.cycle = false;
+        #[cfg(feature = "std")]
+        {
+            self.played_at = None;
         }
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum LoopSetting {
+    #[default]
+    Off,
+    Forever,
+    /// Loops this many more times before stopping, counting down from the start again after
+    /// [`Queue::restart`]
+    Times(u32),
+}
+
+/// What adding to a [`Queue`] at its [`Queue::max_len`] does.
+///
+/// Adds that return `()`, like [`Queue::add_item`], are dropped when there is no room
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum EvictionPolicy {
+    /// Fail with [`QueueError::Full`]
+    #[default]
+    RejectNew,
+    /// Remove the automatically added items furthest from the current one to make room, failing
+    /// if there aren't enough of them
+    DropOldestAuto,
+}
+
+/// Where [`Queue::add_at`] puts new items
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QueuePosition {
+    /// After the last item, AddHere stays where it is
+    End,
+    /// Right after the current item, like [`Queue::add_item_next`]
+    AfterCurrent,
+    /// After the AddHere item, moving the marker to the new item like [`Queue::add_item`]
+    AtAddHere,
+    /// At this index, AddHere stays where it is
+    Index(usize),
+}
+
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ShuffleMode {
+    #[default]
+    Uniform,
+    Weighted,
+    /// Spread out so items with the same key rarely play back to back
+    Spread,
+}
+
+#[derive(Debug)]
 pub struct Queue<
-    T: Debug + Clone + PartialEq, // T: The Singular Item Type
-    U: Debug + PartialEq + Clone + IntoIterator, // U: The Multi-Item Type. Needs to be tracked as multiple items
+    T, // T: The Singular Item Type
+    U, // U: The Multi-Item Type. Needs to be tracked as multiple items
 > {
     pub items: Vec<QueueItem<T, U>>,
     pub played: Vec<QueueItem<T, U>>,
-    pub loop_: bool,
+    loop_: LoopSetting,
+    /// How many times the last [`LoopSetting::Times`] set was for, which [`Queue::restart`]
+    /// counts down from again
+    loop_times: u32,
+    repeat_one: bool,
+    /// The original index of each item in `items`, if the queue is shuffled
     pub shuffle: Option<Vec<usize>>,
+    shuffle_mode: ShuffleMode,
+    rng: Rng,
+    generation: u64,
+    history_limit: Option<usize>,
+    history_dedup: bool,
+    stats: QueueStats,
+    /// Where the AddHere item was last put, `items` is public so it is checked before use
+    addhere_hint: Option<usize>,
+    /// How many of the latest played items passed AddHere on to the item after them, so
+    /// [`Queue::prev`] can hand it back
+    addhere_passed: usize,
+    max_len: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    /// The queue as it was when each open transaction began, innermost last
+    savepoints: Vec<Savepoint>,
+    /// The values of items that left the queue while a transaction was open, by their key
+    buried: Vec<(u64, QueueItemType<T, U>)>,
+    /// The last key given to an item by [`Queue::begin_transaction`]
+    next_key: u64,
 }
 
-// TODO: HAndle the First QueueState[looping] and shuffle
-impl<
-    T: Debug + Clone + PartialEq,
-    U: Debug + PartialEq + Clone + IntoIterator,
-> Queue<T, U> {
-    fn has_addhere(&self) -> bool {
-        for item in &self.items {
-            if item.state == QueueState::AddHere {
-                return true;
+/// Running totals of what a [`Queue`] has done, see [`Queue::stats`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub total_played: usize,
+    pub total_skipped: usize,
+    pub times_looped: usize,
+    pub times_shuffled: usize,
+}
+
+/// Counts of the items in part of a [`Queue`], made by [`Queue::item_stats`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ItemStats {
+    /// The number of entries, with a Multi counting once
+    pub items: usize,
+    /// The number of tracks, with every item of a Multi counting
+    pub tracks: usize,
+    pub by_human: usize,
+    pub automatic: usize,
+}
+
+impl ItemStats {
+    fn count<'a, T: 'a, U: Clone + IntoIterator + 'a>(
+        items: impl Iterator<Item = &'a QueueItem<T, U>>,
+    ) -> Self {
+        let mut stats = ItemStats::default();
+        for item in items {
+            stats.items += 1;
+            stats.tracks += item.item.track_count();
+            if item.by_human {
+                stats.by_human += 1;
+            } else {
+                stats.automatic += 1;
             }
         }
-        false
+        stats
     }
+}
 
-    #[allow(unused)]
-    pub(crate) fn dbg_items(&self) {
-        dbg!(
-            self.items
-                .iter()
-                .map(|item| (&item.item, item.state))
-                .collect::<Vec<(&QueueItemType<T, U>, QueueState)>>(),
-            self.items.len()
-        );
+/// One step that turns an older list of items into the current one, made by [`Queue::diff`].
+///
+/// Deltas are applied in order to the older list, with indices meant for the list as it is at
+/// that point, and `Inserted` and `StateChanged` taking the item at `index` in the current list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueDelta {
+    Inserted { index: usize },
+    Removed { index: usize },
+    Moved { from: usize, to: usize },
+    /// The item kept its place but its state, track, priority or error changed
+    StateChanged { index: usize },
+}
+
+/// What changed between two queues, made by [`Queue::diff_summary`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueDiff<T, U> {
+    /// Upcoming items that weren't in the older queue
+    pub added: Vec<QueueItem<T, U>>,
+    /// Upcoming items of the older queue that are gone
+    pub removed: Vec<QueueItem<T, U>>,
+    /// Whether the items in both are in a different order
+    pub reordered: bool,
+}
+
+/// An owned copy of everything in a [`Queue`], made by [`Queue::checkpoint`].
+///
+/// Open transactions and the generation aren't part of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueSnapshot<T, U> {
+    pub items: Vec<QueueItem<T, U>>,
+    pub played: Vec<QueueItem<T, U>>,
+    pub loop_: LoopSetting,
+    pub repeat_one: bool,
+    pub shuffle: Option<Vec<usize>>,
+    pub shuffle_mode: ShuffleMode,
+    pub history_limit: Option<usize>,
+    pub history_dedup: bool,
+    pub max_len: Option<usize>,
+    pub eviction_policy: EvictionPolicy,
+    pub stats: QueueStats,
+    loop_times: u32,
+    rng: Rng,
+    addhere_passed: usize,
+}
+
+impl<T, U> Queue<T, U> {
+    fn has_addhere(&self) -> bool {
+        self.addhere_index().is_some()
+    }
+
+    /// Finds the AddHere item, without a scan as long as the hint is still right
+    fn addhere_index(&self) -> Option<usize> {
+        let hinted = self
+            .addhere_hint
+            .filter(|&i| self.items.get(i).is_some_and(|item| item.state == QueueState::AddHere));
+        hinted.or_else(|| self.items.iter().position(|item| item.state == QueueState::AddHere))
     }
 
     pub fn new(loop_: bool, shuffle: Option<Vec<usize>>) -> Self {
         Queue {
             items: Vec::new(),
             played: Vec::new(),
-            loop_,
-            shuffle
+            loop_: if loop_ { LoopSetting::Forever } else { LoopSetting::Off },
+            loop_times: 0,
+            repeat_one: false,
+            shuffle,
+            shuffle_mode: ShuffleMode::default(),
+            rng: Rng::default(),
+            generation: 0,
+            history_limit: None,
+            history_dedup: false,
+            stats: QueueStats::default(),
+            addhere_hint: None,
+            addhere_passed: 0,
+            max_len: None,
+            eviction_policy: EvictionPolicy::default(),
+            savepoints: Vec::new(),
+            buried: Vec::new(),
+            next_key: 0,
         }
     }
 
-    pub fn set_items(&mut self, tracks: Vec<QueueItem<T, U>>) {
-        let mut tracks = tracks;
-        self.items.clear();
-        self.items.append(&mut tracks);
+    /// A counter that goes up by one on every call that can change the queue.
+    ///
+    /// Comparing it to an earlier value tells whether the queue may have changed since then
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 
-    /// Inserts an item after the AddHere item
-    pub fn add_item(&mut self, item: T, by_human: bool) {
-        let item = QueueItemType::from_single(item);
-        let mut i: usize = 0;
+    /// Keeps at most `limit` items in `played`, dropping the oldest ones first.
+    ///
+    /// A looping queue starts over from `played`, so this also limits what it can loop over
+    pub fn set_history_limit(&mut self, limit: Option<usize>) {
+        self.generation += 1;
+        self.history_limit = limit;
+        self.trim_history();
+    }
 
-        self.items = self
-            .items
-            .iter()
-            .enumerate()
-            .map(|(j, item_)| {
-                let mut item_ = item_.to_owned();
-                // get the index of the current AddHere item and give it to i
-                if item_.state == QueueState::AddHere {
-                    i = j;
-                    item_.state = QueueState::NoState;
-                }
-                item_
-            })
-            .collect::<Vec<QueueItem<T, U>>>();
-
-        self.items.insert(
-            i + if self.items.is_empty() { 0 } else { 1 },
-            QueueItem {
-                item,
-                state: QueueState::AddHere,
-                by_human,
-            },
-        );
+    /// Drops the oldest played items past the history limit
+    fn trim_history(&mut self) {
+        let over = self.history_limit.and_then(|limit| self.played.len().checked_sub(limit));
+        if let Some(over) = over {
+            let dropped: Vec<_> = self.played.drain(..over).collect();
+            self.bury_all(dropped);
+        }
     }
 
-    /// Inserts an item after the currently playing item
-    pub fn add_item_next(&mut self, item: T) {
-        let item = QueueItemType::from_single(item);
-        use QueueState::*;
-        let empty = self.items.is_empty();
+    pub fn history_limit(&self) -> Option<usize> {
+        self.history_limit
+    }
 
-        self.items.insert(
-            if empty { 0 } else { 1 },
-            QueueItem {
-                item,
-                state: if (self.items.get(1).is_none()
-                    || !self.has_addhere() && self.items.get(1).is_some())
-                    || empty
-                {
-                    AddHere
-                } else {
-                    NoState
-                },
-                by_human: true,
-            },
-        )
+    pub fn history_dedup(&self) -> bool {
+        self.history_dedup
     }
 
-    pub fn add_multi(&mut self, items: Vec<QueueItemType<T, U>>, by_human: bool) {
-        let mut i: usize = 0;
+    /// Limits how many items can be upcoming, with [`Queue::set_eviction_policy`] deciding what
+    /// happens to adds past it.
+    ///
+    /// Lowering it doesn't remove anything already queued. Only adds are limited, so going back
+    /// with [`Queue::prev`], looping and splitting a Multi with [`Queue::flatten`] can go past it
+    pub fn set_max_len(&mut self, max_len: Option<usize>) {
+        self.generation += 1;
+        self.max_len = max_len;
+    }
 
-        self.items = self
-            .items
-            .iter()
-            .enumerate()
-            .map(|(j, item_)| {
-                let mut item_ = item_.to_owned();
-                // get the index of the current AddHere item and give it to i
-                if item_.state == QueueState::AddHere {
-                    i = j;
-                    item_.state = QueueState::NoState;
-                }
-                item_
-            })
-            .collect::<Vec<QueueItem<T, U>>>();
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
 
-        let empty = self.items.is_empty();
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.generation += 1;
+        self.eviction_policy = policy;
+    }
 
-        let len = items.len();
-        for item in items.into_iter().rev() {
-            self.items.insert(
-                i + if empty { 0 } else { 1 },
-                QueueItem {
-                    item,
-                    state: QueueState::NoState,
-                    by_human,
-                },
-            );
-        }
-        self.items[i + len - if empty { 1 } else { 0 }].state = QueueState::AddHere;
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.eviction_policy
     }
 
-    /// Add multiple Items after the currently playing Item
-    pub fn add_multi_next(&mut self, items: Vec<QueueItemType<T, U>>) {
-        use QueueState::*;
-        let empty = self.items.is_empty();
+    pub fn stats(&self) -> &QueueStats {
+        &self.stats
+    }
 
-        let add_here = (self.items.get(1).is_none()
-            || !self.has_addhere() && self.items.get(1).is_some())
-            || empty;
+    pub fn reset_stats(&mut self) {
+        self.generation += 1;
+        self.stats = QueueStats::default();
+    }
 
-        let len = items.len();
+    pub fn enable_loop(&mut self) {
+        self.generation += 1;
+        self.loop_ = LoopSetting::Forever;
+    }
 
-        for item in items {
-            self.items.insert(
-                if empty { 0 } else { 1 },
-                QueueItem {
-                    item,
-                    state: NoState,
-                    by_human: true,
-                },
-            )
-        }
+    pub fn disable_loop(&mut self) {
+        self.generation += 1;
+        self.loop_ = LoopSetting::Off;
+    }
 
-        if add_here {
-            self.items[len - if empty { 1 } else { 0 }].state = QueueState::AddHere;
-        }
+    /// Flips loop mode, returning the new state
+    pub fn toggle_loop(&mut self) -> bool {
+        self.generation += 1;
+        self.loop_ = if self.is_looping() { LoopSetting::Off } else { LoopSetting::Forever };
+        self.is_looping()
     }
 
-    pub fn remove_item(&mut self, remove_index: usize) -> Result<QueueItem<T, U>, QueueError> {
-        // dbg!(/*&remove_index, self.current_index(), &index,*/ &self.items[remove_index]);
+    pub fn is_looping(&self) -> bool {
+        !matches!(self.loop_, LoopSetting::Off | LoopSetting::Times(0))
+    }
 
-        if remove_index < self.items.len() {
-            // update the state of the next item to replace the item being removed
-            if self.items.get(remove_index + 1).is_some() {
-                self.items[remove_index + 1].state = self.items[remove_index].state;
-            }
-            Ok(self.items.remove(remove_index))
-        } else {
-            Err(QueueError::EmptyQueue)
+    pub fn set_loop(&mut self, setting: LoopSetting) {
+        self.generation += 1;
+        self.loop_ = setting;
+        if let LoopSetting::Times(n) = setting {
+            self.loop_times = n;
         }
     }
 
-    pub fn insert(
-        &mut self,
-        index: usize,
-        new_item: QueueItemType<T, U>,
-        addhere: bool,
-    ) -> Result<(), QueueError> {
-        if self.items.get_mut(index).is_none()
-            && index > 0
-            && self.items.get_mut(index - 1).is_none()
-        {
-            return Err(QueueError::OutOfBounds {
-                index,
-                len: self.items.len(),
-            });
-        }
-        if addhere {
-            let mut new_item = QueueItem::from_item_type(new_item);
-            for item in &mut self.items {
-                if item.state == QueueState::AddHere {
-                    item.state = QueueState::NoState
-                }
-            }
-            new_item.state = QueueState::AddHere;
-            self.items.insert(index, new_item);
-        } else {
-            let new_item = QueueItem::from_item_type(new_item);
-            self.items.insert(index, new_item);
+    /// Returns the loop setting, with [`LoopSetting::Times`] counting down as the queue loops
+    pub fn loop_setting(&self) -> LoopSetting {
+        self.loop_
+    }
+
+    /// Returns how many more times the queue will loop, if it loops a limited number of times
+    pub fn loops_remaining(&self) -> Option<u32> {
+        match self.loop_ {
+            LoopSetting::Times(n) => Some(n),
+            _ => None,
         }
-        Ok(())
     }
 
-    pub fn clear(&mut self) {
-        self.items.clear();
+    /// Makes [`Queue::next`] keep returning the current item.
+    ///
+    /// This takes precedence over looping, which only applies again once it is turned off
+    pub fn set_repeat_one(&mut self, repeat: bool) {
+        self.generation += 1;
+        self.repeat_one = repeat;
     }
 
-    pub fn clear_except(&mut self, index: usize) -> Result<(), QueueError> {
-        use QueueState::*;
-        let empty = self.items.is_empty();
+    pub fn is_repeat_one(&self) -> bool {
+        self.repeat_one
+    }
 
-        if !empty && index < self.items.len() {
-            let i = self.items[index].clone();
-            self.items.retain(|item| *item == i);
-            self.items[0].state = AddHere;
-        } else if empty {
-            return Err(QueueError::EmptyQueue);
-        } else {
-            return Err(QueueError::OutOfBounds {
-                index,
-                len: self.items.len(),
-            });
+    /// Seeds the generator used for shuffling, making shuffles reproducible
+    pub fn seed_shuffle(&mut self, seed: u64) {
+        self.generation += 1;
+        self.rng = Rng::new(seed);
+    }
+
+    /// Stops shuffling, keeping the items in their current order
+    pub fn disable_shuffle(&mut self) {
+        self.generation += 1;
+        self.shuffle = None;
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.shuffle.is_some()
+    }
+
+    /// The original, unshuffled index of each item in play order, if the queue is shuffled
+    pub fn play_order(&self) -> Option<&[usize]> {
+        self.shuffle.as_deref()
+    }
+
+    /// Where the item playing at position `n` sits in the unshuffled order
+    pub fn display_index_of_play_position(&self, n: usize) -> Option<usize> {
+        match &self.shuffle {
+            Some(shuffle) => shuffle.get(n).copied(),
+            None => (n < self.items.len()).then_some(n),
         }
-        Ok(())
     }
 
-    pub fn clear_played(&mut self) {
-        self.played.clear();
+    /// When the item at `index` in the unshuffled order will play, 0 being the current item
+    pub fn play_position_of_index(&self, index: usize) -> Option<usize> {
+        match &self.shuffle {
+            Some(shuffle) => shuffle.iter().position(|&original| original == index),
+            None => (index < self.items.len()).then_some(index),
+        }
     }
 
-    pub fn clear_all(&mut self) {
-        self.items.clear();
-        self.played.clear();
+    /// The upcoming items in the order [`Queue::next`] plays them, which is the order of `items`
+    /// whether shuffled or not
+    pub fn iter_play_order(&self) -> impl Iterator<Item = &QueueItem<T, U>> + '_ {
+        self.items.iter()
     }
 
-    pub fn move_to(&mut self, index: usize) -> Result<(), QueueError> {
-        use QueueState::*;
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.shuffle_mode
+    }
 
+    /// The upcoming items, starting with the current one
+    pub fn as_slice(&self) -> &[QueueItem<T, U>] {
+        &self.items
+    }
 
+    /// Up to `n` of the upcoming items in play order, starting with the current one
+    pub fn peek_many(&self, n: usize) -> &[QueueItem<T, U>] {
+        &self.items[..n.min(self.items.len())]
+    }
 
-        let empty = self.items.is_empty();
+    /// The played items, oldest first
+    pub fn played_as_slice(&self) -> &[QueueItem<T, U>] {
+        &self.played
+    }
 
-        let index = if !empty {
-            index
-        } else {
-            return Err(QueueError::EmptyQueue);
-        };
+    /// The played items, oldest first, along with when each was played
+    #[cfg(feature = "std")]
+    pub fn history_with_times(
+        &self,
+    ) -> impl Iterator<Item = (&QueueItem<T, U>, Option<SystemTime>)> + '_ {
+        self.played.iter().map(|item| (item, item.played_at))
+    }
 
-        if !empty && dbg!(index < self.items.len()) {
-            let to_item = self.items[index].clone();
+    /// Removes played items that were played more than `age` ago, returning how many were removed.
+    ///
+    /// Items with no time, like ones put back with [`Queue::restore`], are kept
+    #[cfg(feature = "std")]
+    pub fn trim_played_older_than(&mut self, age: Duration) -> usize {
+        self.generation += 1;
+        let now = SystemTime::now();
+        let (kept, old): (Vec<_>, Vec<_>) =
+            mem::take(&mut self.played).into_iter().partition(|item| {
+                // a time ahead of now means the clock moved back, so count it as just played
+                item.played_at
+                    .is_none_or(|at| now.duration_since(at).map_or(true, |since| since <= age))
+            });
+        self.played = kept;
+        let removed = old.len();
+        self.bury_all(old);
+        removed
+    }
 
-            if let QueueItemType::Multi(_) = to_item.item {
-                unimplemented!(); //TODO: Add logic for multi items
-            }
+    /// Takes the queue apart into its upcoming and played items
+    #[allow(clippy::type_complexity)]
+    pub fn into_vec(self) -> (Vec<QueueItem<T, U>>, Vec<QueueItem<T, U>>) {
+        (self.items, self.played)
+    }
 
-            loop {
-                let empty = self.items.is_empty();
-                let item = self.items[0].item.to_owned();
+    /// Takes the queue apart into every item in playback order, played items first
+    pub fn into_all_items(self) -> Vec<QueueItem<T, U>> {
+        let mut items = self.played;
+        items.extend(self.items);
+        items
+    }
 
-                if item != to_item.item && !empty {
-                    if self.items[0].state == AddHere && self.items.get(1).is_some() {
-                        self.items[1].state = AddHere;
-                    }
-                    let item = self.items.remove(0);
-                    self.played.push(item);
+    /// Whether there is nothing left to play
+    pub fn is_at_end(&self) -> bool {
+        self.items.is_empty()
+    }
 
-                // dbg!(&to_item.item, &self.items[ind].item);
-                } else if empty {
-                    return Err(QueueError::EmptyQueue);
-                } else {
-                    break;
-                }
-            }
-        } else {
-            return Err(QueueError::EmptyQueue);
-        }
-        Ok(())
+    /// Whether nothing has been played yet
+    pub fn is_at_beginning(&self) -> bool {
+        self.played.is_empty()
     }
 
-    pub fn swap(&mut self, a: usize, b: usize) {
-        self.items.swap(a, b)
+    /// How many items have not been played yet, counting the current one
+    pub fn remaining_count(&self) -> usize {
+        self.items.len()
     }
 
-    pub fn move_item(&mut self, from: usize, to: usize) {
-        let item = self.items[from].to_owned();
-        if from != to {
-            self.items.remove(from);
-        }
-        self.items.insert(to, item);
+    /// How many items are in the session, played and upcoming
+    pub fn total_count(&self) -> usize {
+        self.items.len() + self.played.len()
     }
 
-    #[allow(clippy::should_implement_trait)]
-    pub fn next(&mut self) -> Result<&QueueItem<T, U>, QueueError> {
-        if self.items.is_empty() {
-            if self.loop_ {
-                unimplemented!() // TODO: add function to loop the queue
-            } else {
-                return Err(QueueError::EmptyQueue);
-            }
-        }
+    /// Where the current item is in the whole session, counting from 0, which is how many items
+    /// have been played so far
+    pub fn position_of_current(&self) -> usize {
+        self.played.len()
+    }
 
-        if self.items[0].state == QueueState::AddHere || !self.has_addhere() {
-            if let QueueItemType::Multi(_) = self.items[0].item {
-                unimplemented!(); // TODO: Handle Multi items here?
-            }
+    /// [`Queue::position_of_current`] counting from 1, for showing "track 5 of 20"
+    pub fn position_of_current_one_indexed(&self) -> usize {
+        self.played.len() + 1
+    }
 
-            self.items[0].state = QueueState::NoState;
-            if self.items.get_mut(1).is_some() {
-                self.items[1].state = QueueState::AddHere;
-            }
-        }
-        let item = self.items.remove(0);
-        self.played.push(item);
+    /// Whether any upcoming or played item is a Multi
+    pub fn has_multi_items(&self) -> bool {
+        self.items
+            .iter()
+            .chain(&self.played)
+            .any(|item| matches!(item.item, QueueItemType::Multi(_)))
+    }
 
-        if self.items.is_empty() {
-            Err(QueueError::NoNext)
-        } else {
-            Ok(&self.items[0])
-        }
+    /// Whether any upcoming or played item is a Single
+    pub fn has_single_items(&self) -> bool {
+        self.items
+            .iter()
+            .chain(&self.played)
+            .any(|item| matches!(item.item, QueueItemType::Single(_)))
     }
 
-    pub fn prev(&mut self) -> Result<&QueueItem<T, U>, QueueError> {
-        if let Some(item) = self.played.pop() {
-            if item.state == QueueState::First && self.loop_ {
-                todo!()
-            }
+    /// Checks the invariants every method is meant to keep, reporting the first one broken
+    pub fn validate(&self) -> Result<(), QueueIntegrityError> {
+        use QueueState::*;
 
-            if let QueueItemType::Multi(_) = self.items[0].item {
-                unimplemented!(); // TODO: Handle Multi items here?
-            } if let QueueItemType::Multi(_) = item.item {
-                unimplemented!(); // TODO: Handle Multi items here?
+        for state in [AddHere, First] {
+            let mut found = self.items.iter().enumerate().filter(|(_, item)| item.state == state);
+            if let (Some((first, _)), Some((second, _))) = (found.next(), found.next()) {
+                return Err(QueueIntegrityError::DuplicateState { state, first, second });
             }
+        }
 
-            self.items.insert(0, item);
-            Ok(&self.items[0])
-        } else {
-            Err(QueueError::EmptyPlayed)
+        // played items belong in `played`, so index 0 is always the current item
+        let played = |item: &QueueItem<T, U>| matches!(item.state, Played | Skipped);
+        if let Some(index) = self.items.iter().position(played) {
+            return Err(QueueIntegrityError::PlayedUpcoming { index });
         }
-    }
 
-    pub fn current(&self) -> Result<&QueueItem<T, U>, QueueError> {
-        if !self.items.is_empty() {
-            if let QueueItemType::Multi(_) = self.items[0].item {
-                unimplemented!(); // TODO: Handle Multi items here?
+        if let Some(shuffle) = &self.shuffle {
+            if shuffle.len() != self.items.len() {
+                return Err(QueueIntegrityError::ShuffleLength {
+                    len: shuffle.len(),
+                    items: self.items.len(),
+                });
+            }
+            let mut seen = vec![false; shuffle.len()];
+            for (index, &original) in shuffle.iter().enumerate() {
+                match seen.get_mut(original) {
+                    Some(seen) if !*seen => *seen = true,
+                    _ => return Err(QueueIntegrityError::ShuffleNotPermutation { index }),
+                }
             }
-            Ok(&self.items[0])
-        } else {
-            Err(QueueError::EmptyQueue)
         }
+        Ok(())
     }
+}
 
-    pub fn check_played(&mut self, limit: usize) {
-        while self.played.len() > limit {
-            self.played.remove(0);
-        }
+impl<T, U> Default for Queue<T, U> {
+    fn default() -> Self {
+        Queue::new(false, None)
     }
 }
 
-
-use thiserror::Error;
-
-#[derive(Error, Debug)]
-pub enum QueueError {
-    #[error("Index out of bounds! Index {index} is over len {len}")]
-    OutOfBounds { index: usize, len: usize },
-    #[error("The Queue is empty!")]
-    EmptyQueue,
-    #[error("There are no past played songs!")]
-    EmptyPlayed,
-    #[error("There is no item after this in the Queue")]
-    NoNext,
+impl<
+    T: Debug + Clone + PartialEq,
+    U: Debug + PartialEq + Clone + IntoIterator,
+> From<Vec<T>> for Queue<T, U> {
+    /// Makes a queue of Single items, as if they were added with [`Queue::extend_singles`]
+    fn from(items: Vec<T>) -> Self {
+        let mut queue = Queue::default();
+        // a default queue has no length limit to run into
+        let _ = queue.extend_singles(items, false);
+        queue
+    }
 }
+
+// TODO: HAndle the First QueueState[looping] and shuffle
+impl<
+    T: Debug + Clone + PartialEq,
+    U: Debug + PartialEq + Clone + IntoIterator,
+> Queue<T, U> {
+    /// Starts the queue over from the played items if looping allows it, returning whether it did
+    fn try_loop(&mut self) -> bool {
+        if self.played.is_empty() {
+            return false;
+        }
+        match self.loop_ {
+            LoopSetting::Off | LoopSetting::Times(0) => return false,
+            LoopSetting::Times(n) => self.loop_ = LoopSetting::Times(n - 1),
+            LoopSetting::Forever => (),
+        }
+        // repeats and removed items went into `played` too, but aren't part of the cycle, and
+        // history from elsewhere is looped over whole
+        let in_cycle = self.played.iter().any(|item| item.tag.cycle);
+        if self.shuffle.is_some() && in_cycle {
+            self.reshuffle_cycle();
+        } else {
+            if in_cycle {
+                let (cycle, others) = mem::take(&mut self.played)
+                    .into_iter()
+                    .partition(|item| item.tag.cycle);
+                self.played = cycle;
+                self.bury_all(others);
+            }
+            self.restart_items();
+        }
+        self.stats.times_looped += 1;
+        true
+    }
+
+    /// Starts a shuffled loop over with a fresh order of the items played in the last cycle.
+    ///
+    /// The last cycle stays in `played` so [`Queue::prev`] can still go back into it, and the new
+    /// cycle avoids starting with the item that just played. The order it was played in becomes
+    /// the unshuffled order, and weighted shuffles are redone uniformly
+    fn reshuffle_cycle(&mut self) {
+        let cycle: Vec<&QueueItem<T, U>> =
+            self.played.iter().filter(|item| item.tag.cycle).collect();
+        let order = Self::cycle_order(&mut self.rng, &cycle);
+
+        self.items = order
+            .iter()
+            .map(|&i| {
+                let mut item = cycle[i].clone();
+                item.requeue();
+                item
+            })
+            .collect();
+        self.items[0].state = QueueState::AddHere;
+        self.shuffle = Some(order);
+        self.shuffle_mode = ShuffleMode::Uniform;
+        for item in &mut self.played {
+            item.tag.cycle = false;
+        }
+        self.stats.times_shuffled += 1;
+    }
+
+    /// A random order for the next cycle of a shuffled loop, that doesn't start with the item
+    /// that played last if it can help it
+    fn cycle_order(rng: &mut Rng, cycle: &[&QueueItem<T, U>]) -> Vec<usize> {
+        let len = cycle.len();
+        let mut order: Vec<usize> = (0..len).collect();
+        for i in (1..len).rev() {
+            let j = rng.below(i + 1);
+            order.swap(i, j);
+        }
+
+        let Some(last) = cycle.last().map(|item| &item.item) else {
+            return order;
+        };
+        if cycle[order[0]].item == *last {
+            let others: Vec<usize> = (1..len).filter(|&i| cycle[order[i]].item != *last).collect();
+            if !others.is_empty() {
+                let j = others[rng.below(others.len())];
+                order.swap(0, j);
+            }
+        }
+        order
+    }
+
+    /// Randomly reorders every item after the current one
+    pub fn enable_shuffle(&mut self) {
+        self.generation += 1;
+        let mut order: Vec<usize> = (0..self.items.len()).collect();
+        for i in (2..order.len()).rev() {
+            let j = 1 + self.rng.below(i);
+            order.swap(i, j);
+        }
+        self.apply_shuffle(order, ShuffleMode::Uniform);
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Reorders every item after the current one so that higher weighted items tend to come first.
+    ///
+    /// `weights` is indexed by position in `items`, missing weights count as `1.0`. NaN and weights
+    /// that aren't positive count as the smallest weight, and infinite ones as the biggest
+    pub fn enable_weighted_shuffle<W: Into<f64>>(&mut self, weights: Vec<W>) {
+        self.generation += 1;
+        // small enough that the weights can't add up to infinity
+        let max = f64::MAX / self.items.len().max(1) as f64;
+        let mut weights = weights.into_iter().map(|weight| match weight.into() {
+            weight if weight.is_nan() => f64::EPSILON,
+            weight => weight.clamp(f64::EPSILON, max),
+        });
+        let mut remaining: Vec<(usize, f64)> = (0..self.items.len())
+            .map(|i| (i, weights.next().unwrap_or(1.0)))
+            .collect();
+
+        // the current item never moves
+        let mut order = Vec::with_capacity(remaining.len());
+        if !remaining.is_empty() {
+            order.push(remaining.remove(0).0);
+        }
+
+        // weighted sampling without replacement, one pick at a time
+        while !remaining.is_empty() {
+            let total: f64 = remaining.iter().map(|(_, weight)| weight).sum();
+            let mut target = self.rng.next_f64() * total;
+            let pick = remaining
+                .iter()
+                .position(|(_, weight)| {
+                    target -= weight;
+                    target <= 0.0
+                })
+                .unwrap_or(remaining.len() - 1);
+            order.push(remaining.remove(pick).0);
+        }
+        self.apply_shuffle(order, ShuffleMode::Weighted);
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Like [`Queue::enable_weighted_shuffle`], with each item's weight coming from `weight`
+    pub fn shuffle_weighted<F: Fn(&QueueItemType<T, U>) -> f64>(&mut self, weight: F) {
+        let weights = self.items.iter().map(|item| weight(&item.item)).collect();
+        self.enable_weighted_shuffle::<f64>(weights);
+    }
+
+    /// Randomly reorders every item after the current one, keeping items with the same key apart
+    /// where it can.
+    ///
+    /// Each pick comes from the key with the most items left that isn't the key just placed, so
+    /// when one key has too many items they end up next to each other at the end
+    pub fn shuffle_spread_by<K: Eq, F: Fn(&QueueItem<T, U>) -> K>(&mut self, key: F) {
+        self.generation += 1;
+        if self.items.is_empty() {
+            self.apply_shuffle(Vec::new(), ShuffleMode::Spread);
+            return;
+        }
+
+        let mut groups: Vec<(K, Vec<usize>)> = Vec::new();
+        for i in 1..self.items.len() {
+            let k = key(&self.items[i]);
+            match groups.iter_mut().find(|(group, _)| *group == k) {
+                Some((_, members)) => members.push(i),
+                None => groups.push((k, vec![i])),
+            }
+        }
+        for (_, members) in &mut groups {
+            for i in (1..members.len()).rev() {
+                let j = self.rng.below(i + 1);
+                members.swap(i, j);
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.items.len());
+        order.push(0);
+        let mut last = groups.iter().position(|(group, _)| *group == key(&self.items[0]));
+        while order.len() < self.items.len() {
+            // the biggest group left other than the last one, ties picked at random
+            let most = groups
+                .iter()
+                .enumerate()
+                .filter(|&(g, _)| Some(g) != last)
+                .map(|(_, (_, members))| members.len())
+                .max()
+                .unwrap_or(0);
+            let candidates: Vec<usize> = (0..groups.len())
+                .filter(|&g| Some(g) != last && most > 0 && groups[g].1.len() == most)
+                .collect();
+            let g = if candidates.is_empty() {
+                // only the last key is left, so it has to repeat
+                last.unwrap()
+            } else {
+                candidates[self.rng.below(candidates.len())]
+            };
+            order.push(groups[g].1.pop().unwrap());
+            last = Some(g);
+        }
+        self.apply_shuffle(order, ShuffleMode::Spread);
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Reorders `items` so that `items[i]` is the old `items[order[i]]`
+    fn apply_shuffle(&mut self, order: Vec<usize>, mode: ShuffleMode) {
+        let mut items: Vec<Option<QueueItem<T, U>>> =
+            mem::take(&mut self.items).into_iter().map(Some).collect();
+        self.items = order.iter().map(|&i| items[i].take().unwrap()).collect();
+
+        // keep pointing at the unshuffled positions when shuffling twice
+        self.shuffle = Some(match self.shuffle.take() {
+            Some(old) if old.len() == order.len() => order.iter().map(|&i| old[i]).collect(),
+            _ => order,
+        });
+        self.shuffle_mode = mode;
+        self.stats.times_shuffled += 1;
+    }
+
+    /// Puts the items back in their order from before shuffling.
+    ///
+    /// The current item keeps playing, with the others after it in their original order. Nothing
+    /// goes into `played`, since none of them have been played yet, and every item keeps its
+    /// marker
+    pub fn unshuffle(&mut self) {
+        self.generation += 1;
+        let Some(order) = self.shuffle.take() else {
+            return;
+        };
+
+        let mut restored: Vec<Option<QueueItem<T, U>>> = vec![None; self.items.len()];
+        let valid = order.len() == self.items.len()
+            && order.iter().all(|&i| i < restored.len())
+            && {
+                let mut seen = vec![false; order.len()];
+                order.iter().all(|&i| !mem::replace(&mut seen[i], true))
+            };
+        // the items were changed behind our back, so there is no order to go back to
+        if !valid {
+            return;
+        }
+
+        let current = order.first().copied().unwrap_or(0);
+        for (item, i) in mem::take(&mut self.items).into_iter().zip(order) {
+            restored[i] = Some(item);
+        }
+        let current = restored.get_mut(current).and_then(Option::take);
+        self.items = current.into_iter().chain(restored.into_iter().flatten()).collect();
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Keeps the shuffle order in sync with an item inserted at `index`
+    fn shuffle_inserted(&mut self, index: usize) {
+        if let Some(shuffle) = &mut self.shuffle {
+            if index > shuffle.len() {
+                return;
+            }
+            // new items go right after their neighbour in the unshuffled order too
+            let original = if index == 0 { 0 } else { shuffle[index - 1] + 1 };
+            for i in shuffle.iter_mut() {
+                if *i >= original {
+                    *i += 1;
+                }
+            }
+            shuffle.insert(index, original);
+        }
+    }
+
+    /// Keeps the shuffle order in sync with the item at `index` being removed
+    fn shuffle_removed(&mut self, index: usize) {
+        if let Some(shuffle) = &mut self.shuffle {
+            if index >= shuffle.len() {
+                return;
+            }
+            let original = shuffle.remove(index);
+            for i in shuffle.iter_mut() {
+                if *i > original {
+                    *i -= 1;
+                }
+            }
+        }
+    }
+
+    /// Replaces the upcoming items, with the first one marked `First` and the last one `AddHere`
+    /// the same as [`QueueBuilder::build`].
+    ///
+    /// Whatever states the items came with are cleared, and the queue is no longer shuffled
+    pub fn set_items(&mut self, tracks: Vec<QueueItem<T, U>>) {
+        self.generation += 1;
+        let old = mem::replace(&mut self.items, tracks);
+        self.bury_all(old);
+        for item in &mut self.items {
+            item.state = QueueState::NoState;
+            item.track = 0;
+            item.tag.key = 0;
+        }
+        if let Some(first) = self.items.first_mut() {
+            first.state = QueueState::First;
+        }
+        if let Some(last) = self.items.last_mut() {
+            last.state = QueueState::AddHere;
+        }
+        self.addhere_hint = self.items.len().checked_sub(1);
+        self.shuffle = None;
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Inserts an item after the AddHere item.
+    ///
+    /// Fails with [`QueueError::Full`] if there isn't room under [`Queue::max_len`], adding nothing
+    pub fn add_item(&mut self, item: T, by_human: bool) -> Result<(), QueueError> {
+        self.add_at(QueueItemType::from_single(item), QueuePosition::AtAddHere, by_human)
+    }
+
+    /// Inserts an item after the AddHere item like [`Queue::add_item`], with a priority for
+    /// [`Queue::sort_by_priority`]
+    pub fn add_item_with_priority(
+        &mut self,
+        item: T,
+        by_human: bool,
+        priority: i32,
+    ) -> Result<(), QueueError> {
+        let item = QueueItem::from_item_type(QueueItemType::from_single(item), by_human)
+            .with_priority(priority);
+        self.add_queue_items_at(vec![item], QueuePosition::AtAddHere)
+    }
+
+    /// Inserts an item after the currently playing item.
+    ///
+    /// The new item only takes the AddHere marker if no upcoming item has it, moving it off the
+    /// current item if needed, so the marker is never duplicated. Fails with
+    /// [`QueueError::Full`] if there isn't room under [`Queue::max_len`], adding nothing
+    pub fn add_item_next(&mut self, item: T) -> Result<(), QueueError> {
+        self.add_at(QueueItemType::from_single(item), QueuePosition::AfterCurrent, true)
+    }
+
+    /// Inserts multiple items after the AddHere item, keeping their order.
+    ///
+    /// Fails with [`QueueError::Full`] if there isn't room under [`Queue::max_len`], adding nothing
+    pub fn add_multi(
+        &mut self,
+        items: Vec<QueueItemType<T, U>>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.add_multi_at(items, QueuePosition::AtAddHere, by_human)
+    }
+
+    /// Adds every Single after the AddHere item, the same as [`Queue::add_multi`]
+    pub fn extend_singles<I: IntoIterator<Item = T>>(
+        &mut self,
+        iter: I,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.add_multi(iter.into_iter().map(QueueItemType::Single).collect(), by_human)
+    }
+
+    /// Adds every Multi after the AddHere item, the same as [`Queue::add_multi`]
+    pub fn extend_groups<I: IntoIterator<Item = U>>(
+        &mut self,
+        iter: I,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.add_multi(iter.into_iter().map(QueueItemType::Multi).collect(), by_human)
+    }
+
+    /// Inserts an item at `position`, see [`QueuePosition`] for how each one treats AddHere
+    pub fn add_at(
+        &mut self,
+        item: QueueItemType<T, U>,
+        position: QueuePosition,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.add_multi_at(vec![item], position, by_human)
+    }
+
+    /// Inserts multiple items at `position`, keeping their order.
+    ///
+    /// Fails if a [`QueuePosition::Index`] is past the end of the queue, or with
+    /// [`QueueError::Full`] if there isn't room for all of them under [`Queue::max_len`]
+    pub fn add_multi_at(
+        &mut self,
+        items: Vec<QueueItemType<T, U>>,
+        position: QueuePosition,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        let items = items
+            .into_iter()
+            .map(|item| QueueItem::from_item_type(item, by_human))
+            .collect();
+        self.add_queue_items_at(items, position)
+    }
+
+    /// Adds already made items at `position`, keeping their `by_human` and metadata.
+    ///
+    /// Their states and tracks are reset, the queue picks the states the same way as in
+    /// [`Queue::add_multi_at`]
+    pub fn add_queue_items_at(
+        &mut self,
+        mut items: Vec<QueueItem<T, U>>,
+        position: QueuePosition,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        for item in &mut items {
+            item.state = QueueState::NoState;
+            item.track = 0;
+            item.tag.key = 0;
+        }
+
+        // only an index has to stay put while room is made, the other positions are found after
+        let at = match position {
+            QueuePosition::Index(index) => {
+                self.check_insert_index(index)?;
+                index
+            }
+            _ => 0,
+        };
+        let at = self.make_room(items.len(), at)?;
+
+        let index = match position {
+            QueuePosition::AtAddHere => {
+                self.splice_at_addhere(items);
+                debug_assert!(self.validate().is_ok());
+                return Ok(());
+            }
+            QueuePosition::AfterCurrent => {
+                self.splice_next(items);
+                debug_assert!(self.validate().is_ok());
+                return Ok(());
+            }
+            QueuePosition::End => self.items.len(),
+            QueuePosition::Index(_) => at,
+        };
+
+        // an empty queue still needs somewhere to add after
+        if self.items.is_empty() {
+            if let Some(last) = items.last_mut() {
+                last.state = QueueState::AddHere;
+            }
+        }
+        self.splice_items(index, items);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Adds items from every batch in turn after the AddHere item, until all batches run out.
+    ///
+    /// Fails with [`QueueError::Full`] if there isn't room under [`Queue::max_len`], adding nothing
+    pub fn add_interleaved(
+        &mut self,
+        batches: Vec<Vec<QueueItemType<T, U>>>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        let mut batches: Vec<_> = batches.into_iter().map(Vec::into_iter).collect();
+        let mut items = Vec::new();
+
+        loop {
+            let before = items.len();
+            for batch in &mut batches {
+                if let Some(item) = batch.next() {
+                    items.push(QueueItem::from_item_type(item, by_human));
+                }
+            }
+            if items.len() == before {
+                break;
+            }
+        }
+        self.make_room(items.len(), 0)?;
+        self.splice_at_addhere(items);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Adds every `(item, by_human)` pair after the AddHere item, in order.
+    ///
+    /// This is the same as calling [`Queue::add_item`] for each one, but only touches `items` once,
+    /// and adds none of them if there isn't room for all of them
+    pub fn batch_add<I: IntoIterator<Item = (QueueItemType<T, U>, bool)>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        let items = iter
+            .into_iter()
+            .map(|(item, by_human)| QueueItem::from_item_type(item, by_human))
+            .collect::<Vec<_>>();
+        self.make_room(items.len(), 0)?;
+        self.splice_at_addhere(items);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Inserts all of `items` after the AddHere item in one go, moving the marker to the last one
+    fn splice_at_addhere(&mut self, mut items: Vec<QueueItem<T, U>>) {
+        use QueueState::*;
+
+        let Some(last) = items.last_mut() else {
+            return;
+        };
+        last.state = AddHere;
+
+        let index = match self.addhere_index() {
+            Some(i) => {
+                self.items[i].state = NoState;
+                i + 1
+            }
+            None => self.items.len().min(1),
+        };
+        let len = items.len();
+        self.splice_items(index, items);
+        self.addhere_hint = Some(index + len - 1);
+    }
+
+    /// Inserts all of `items` after the current item, the last one claiming AddHere if no upcoming
+    /// item has it
+    fn splice_next(&mut self, mut items: Vec<QueueItem<T, U>>) {
+        use QueueState::*;
+
+        let claim = self.addhere_index().is_none_or(|i| i == 0);
+        let Some(last) = items.last_mut() else {
+            return;
+        };
+
+        if claim {
+            last.state = AddHere;
+            if let Some(current) = self.items.first_mut().filter(|item| item.state == AddHere) {
+                current.state = NoState;
+            }
+        }
+        let index = self.items.len().min(1);
+        let len = items.len();
+        self.splice_items(index, items);
+        if claim {
+            self.addhere_hint = Some(index + len - 1);
+        }
+    }
+
+    fn splice_items(&mut self, index: usize, items: Vec<QueueItem<T, U>>) {
+        let len = items.len();
+        if let Some(hint) = self.addhere_hint.as_mut().filter(|hint| **hint >= index) {
+            *hint += len;
+        }
+        self.items.splice(index..index, items);
+        for i in index..index + len {
+            self.shuffle_inserted(i);
+        }
+    }
+
+    /// Add multiple Items after the currently playing Item, keeping their order.
+    ///
+    /// The last one takes the AddHere marker the same way [`Queue::add_item_next`] does, and
+    /// like it fails with [`QueueError::Full`] when there isn't room
+    pub fn add_multi_next(&mut self, items: Vec<QueueItemType<T, U>>) -> Result<(), QueueError> {
+        self.add_multi_at(items, QueuePosition::AfterCurrent, true)
+    }
+
+    pub fn remove_item(&mut self, remove_index: usize) -> Result<QueueItem<T, U>, QueueError> {
+        self.generation += 1;
+        if remove_index < self.items.len() {
+            // update the state of the next item to replace the item being removed
+            if self.items.get(remove_index + 1).is_some() {
+                self.items[remove_index + 1].state = self.items[remove_index].state;
+            }
+            self.shuffle_removed(remove_index);
+            let removed = self.items.remove(remove_index);
+            self.bury_copy(&removed);
+            if remove_index == 0 {
+                self.pass_errored();
+            }
+            debug_assert!(self.validate().is_ok());
+            Ok(removed)
+        } else {
+            Err(QueueError::EmptyQueue)
+        }
+    }
+
+    /// Removes the current item, returning it along with the item that is now current.
+    ///
+    /// The next item takes the AddHere marker if the removed item had it. With `to_played` set a
+    /// copy of the removed item goes into `played`, as if it had been skipped
+    #[allow(clippy::type_complexity)]
+    pub fn remove_current(
+        &mut self,
+        to_played: bool,
+    ) -> Result<(QueueItem<T, U>, Option<&QueueItem<T, U>>), QueueError> {
+        use QueueState::*;
+
+        self.generation += 1;
+        if self.items.is_empty() {
+            return Err(QueueError::EmptyQueue);
+        }
+
+        let removed = self.items.remove(0);
+        self.bury_copy(&removed);
+        self.shuffle_removed(0);
+        if !self.has_addhere() {
+            if let Some(current) = self.items.first_mut() {
+                current.state = AddHere;
+            }
+        }
+        if to_played {
+            let copy = QueueItem {
+                state: Skipped,
+                ..removed.clone()
+            };
+            self.push_played(copy, false);
+        }
+        self.pass_errored();
+        debug_assert!(self.validate().is_ok());
+        Ok((removed, self.items.first()))
+    }
+
+    /// Removes every item that is equal to an earlier item, returning how many were removed
+    pub fn dedup(&mut self) -> usize {
+        self.dedup_by_key(|item| item.clone())
+    }
+
+    /// Removes every item whose key matches an earlier item's, returning how many were removed
+    pub fn dedup_by_key<K: PartialEq, F: FnMut(&QueueItemType<T, U>) -> K>(
+        &mut self,
+        f: F,
+    ) -> usize {
+        self.generation += 1;
+        let removed = self.dedup_items_by_key(f);
+        debug_assert!(self.validate().is_ok());
+        removed
+    }
+
+    fn dedup_items_by_key<K: PartialEq, F: FnMut(&QueueItemType<T, U>) -> K>(
+        &mut self,
+        mut f: F,
+    ) -> usize {
+        let mut seen: Vec<K> = Vec::new();
+        let mut removed = 0;
+        let mut i = 0;
+
+        while i < self.items.len() {
+            let key = f(&self.items[i].item);
+            if seen.contains(&key) {
+                let item = self.items.remove(i);
+                self.shuffle_removed(i);
+                // the marker moves back onto the item the duplicate was queued after
+                if item.state == QueueState::AddHere {
+                    self.items[i - 1].state = QueueState::AddHere;
+                }
+                self.bury(item);
+                removed += 1;
+            } else {
+                seen.push(key);
+                i += 1;
+            }
+        }
+        removed
+    }
+
+    /// Removes upcoming items that are already in `played`, then dedups the rest like
+    /// [`Queue::dedup`], returning how many were removed.
+    ///
+    /// The current item is kept even if it was played before
+    pub fn remove_duplicates(&mut self) -> usize {
+        self.generation += 1;
+        let mut removed = 0;
+        let mut i = 1;
+
+        while i < self.items.len() {
+            if self.played.iter().any(|played| played.item == self.items[i].item) {
+                let item = self.items.remove(i);
+                self.shuffle_removed(i);
+                // the same as dedup, the marker moves back onto the item before
+                if item.state == QueueState::AddHere {
+                    self.items[i - 1].state = QueueState::AddHere;
+                }
+                self.bury(item);
+                removed += 1;
+            } else {
+                i += 1;
+            }
+        }
+        removed += self.dedup_items_by_key(|item| item.clone());
+        debug_assert!(self.validate().is_ok());
+        removed
+    }
+
+    /// Inserts an item right before `items[index]`, taking over its AddHere marker if it had one
+    pub fn add_item_before(
+        &mut self,
+        index: usize,
+        item: QueueItemType<T, U>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        use QueueState::*;
+
+        self.generation += 1;
+        if index >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        let index = self.make_room_keeping(1, index, Some(index))?;
+
+        let state = match self.items.get_mut(index) {
+            Some(next) if next.state == AddHere => {
+                next.state = NoState;
+                AddHere
+            }
+            _ => NoState,
+        };
+        self.items.insert(index, QueueItem::from_item_type(item, by_human).with_state(state));
+        self.shuffle_inserted(index);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Inserts an item right after `items[index]`, without moving the AddHere marker.
+    ///
+    /// On an empty queue `index` must be 0, and the item gets the marker
+    pub fn add_item_after(
+        &mut self,
+        index: usize,
+        item: QueueItemType<T, U>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        let empty = self.items.is_empty();
+        if index >= self.items.len() && !(empty && index == 0) {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+
+        let index = match empty {
+            true => self.make_room(1, 0)?,
+            false => self.make_room_keeping(1, index, Some(index))? + 1,
+        };
+        let state = if empty { QueueState::AddHere } else { QueueState::NoState };
+        self.items.insert(index, QueueItem::from_item_type(item, by_human).with_state(state));
+        self.shuffle_inserted(index);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    pub fn insert(
+        &mut self,
+        index: usize,
+        new_item: QueueItemType<T, U>,
+        by_human: bool,
+        addhere: bool,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        self.check_insert_index(index)?;
+        let index = self.make_room(1, index)?;
+        if addhere {
+            let mut new_item = QueueItem::from_item_type(new_item, by_human);
+            for item in &mut self.items {
+                if item.state == QueueState::AddHere {
+                    item.state = QueueState::NoState
+                }
+            }
+            new_item.state = QueueState::AddHere;
+            self.items.insert(index, new_item);
+        } else {
+            let new_item = QueueItem::from_item_type(new_item, by_human);
+            self.items.insert(index, new_item);
+        }
+        self.shuffle_inserted(index);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Inserts all of `items` starting at `index`, in order.
+    ///
+    /// Like [`Queue::insert`], `addhere` moves the AddHere marker onto the first new item
+    pub fn insert_range(
+        &mut self,
+        index: usize,
+        items: Vec<QueueItemType<T, U>>,
+        by_human: bool,
+        addhere: bool,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        self.check_insert_index(index)?;
+        let index = self.make_room(items.len(), index)?;
+        let mut items: Vec<QueueItem<T, U>> = items
+            .into_iter()
+            .map(|item| QueueItem::from_item_type(item, by_human))
+            .collect();
+
+        if let Some(first) = items.first_mut().filter(|_| addhere) {
+            first.state = QueueState::AddHere;
+            for item in &mut self.items {
+                if item.state == QueueState::AddHere {
+                    item.state = QueueState::NoState
+                }
+            }
+        }
+        self.splice_items(index, items);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Makes room for `count` more items under the length limit, returning where `at` is after
+    /// any evictions.
+    ///
+    /// Either enough items are evicted or none are, and the current item is never evicted
+    fn make_room(&mut self, count: usize, at: usize) -> Result<usize, QueueError> {
+        self.make_room_keeping(count, at, None)
+    }
+
+    /// [`Queue::make_room`] that doesn't evict `items[keep]` either, for adds that go next to it
+    fn make_room_keeping(
+        &mut self,
+        count: usize,
+        at: usize,
+        keep: Option<usize>,
+    ) -> Result<usize, QueueError> {
+        let Some(max) = self.max_len else {
+            return Ok(at);
+        };
+        let over = (self.items.len() + count).saturating_sub(max);
+        if over == 0 {
+            return Ok(at);
+        }
+        if self.eviction_policy == EvictionPolicy::RejectNew {
+            return Err(QueueError::Full);
+        }
+
+        // automatically added items furthest from the current one go first
+        let evict: Vec<usize> = (1..self.items.len())
+            .rev()
+            .filter(|&i| !self.items[i].by_human && Some(i) != keep)
+            .take(over)
+            .collect();
+        if evict.len() < over {
+            return Err(QueueError::Full);
+        }
+        for &i in &evict {
+            let item = self.items.remove(i);
+            self.shuffle_removed(i);
+            if item.state == QueueState::AddHere {
+                self.items[i - 1].state = QueueState::AddHere;
+            }
+            self.bury(item);
+        }
+        Ok(at - evict.iter().filter(|&&i| i < at).count())
+    }
+
+    /// Something can be inserted anywhere up to and including the end
+    fn check_insert_index(&self, index: usize) -> Result<(), QueueError> {
+        if self.items.get(index).is_none() && index > 0 && self.items.get(index - 1).is_none() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.generation += 1;
+        let items = mem::take(&mut self.items);
+        self.bury_all(items);
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.clear();
+        }
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Removes every item after the current one and returns them, leaving `played` alone.
+    ///
+    /// The current item keeps playing and takes the AddHere marker
+    pub fn clear_upcoming(&mut self) -> Vec<QueueItem<T, U>> {
+        self.generation += 1;
+        if self.items.is_empty() {
+            return Vec::new();
+        }
+
+        let removed = self.items.split_off(1);
+        for item in &removed {
+            self.bury_copy(item);
+        }
+        if let Some(shuffle) = &mut self.shuffle {
+            // the current item is the only one left, so it is first in any order
+            shuffle.clear();
+            shuffle.push(0);
+        }
+        self.items[0].state = QueueState::AddHere;
+        debug_assert!(self.validate().is_ok());
+        removed
+    }
+
+    pub fn clear_except(&mut self, index: usize) -> Result<(), QueueError> {
+        use QueueState::*;
+        self.generation += 1;
+        let empty = self.items.is_empty();
+
+        if !empty && index < self.items.len() {
+            // only the item at `index` stays, even if others are equal to it
+            for j in (0..self.items.len()).rev() {
+                if j != index {
+                    self.shuffle_removed(j);
+                }
+            }
+            let mut kept = self.items.swap_remove(index);
+            kept.state = AddHere;
+            let others = mem::replace(&mut self.items, vec![kept]);
+            self.bury_all(others);
+            self.addhere_hint = Some(0);
+            debug_assert!(self.validate().is_ok());
+        } else if empty {
+            return Err(QueueError::EmptyQueue);
+        } else {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Takes every item out of `played`, oldest first
+    pub fn drain_played(&mut self) -> impl Iterator<Item = QueueItem<T, U>> + '_ {
+        self.generation += 1;
+        self.bury_played_copies();
+        self.played.drain(..)
+    }
+
+    /// Takes the whole of `played`, oldest first, leaving it empty
+    pub fn take_played(&mut self) -> Vec<QueueItem<T, U>> {
+        self.generation += 1;
+        self.bury_played_copies();
+        mem::take(&mut self.played)
+    }
+
+    pub fn clear_played(&mut self) {
+        self.generation += 1;
+        let played = mem::take(&mut self.played);
+        self.bury_all(played);
+    }
+
+    pub fn clear_all(&mut self) {
+        self.clear();
+        let played = mem::take(&mut self.played);
+        self.bury_all(played);
+    }
+
+    /// Moves every played item back in front of the queue, in the order they were played.
+    ///
+    /// A [`LoopSetting::Times`] countdown starts over too
+    pub fn restart(&mut self) {
+        self.generation += 1;
+        if let LoopSetting::Times(_) = self.loop_ {
+            self.loop_ = LoopSetting::Times(self.loop_times);
+        }
+        self.restart_items();
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Queues every played item again after the last upcoming one, oldest first, emptying
+    /// `played`.
+    ///
+    /// If nothing was upcoming, the first of them becomes the current item and takes AddHere.
+    /// Like other adds, it fails with [`QueueError::Full`] and leaves both lists alone if there
+    /// isn't room for all of them under [`Queue::max_len`]
+    pub fn extend_from_played(&mut self) -> Result<(), QueueError> {
+        self.generation += 1;
+        self.make_room(self.played.len(), 0)?;
+        let empty = self.items.is_empty();
+        let mut items = mem::take(&mut self.played);
+        for item in &mut items {
+            item.requeue();
+        }
+        if let Some(first) = items.first_mut().filter(|_| empty) {
+            first.state = QueueState::AddHere;
+        }
+        self.splice_items(self.items.len(), items);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    fn restart_items(&mut self) {
+        use QueueState::*;
+
+        if self.played.is_empty() {
+            return;
+        }
+
+        let mut items = mem::take(&mut self.played);
+        for item in &mut items {
+            item.requeue();
+        }
+        let restored = items.len();
+        for item in &mut self.items {
+            if item.state == First {
+                item.state = NoState;
+            }
+        }
+        items.append(&mut self.items);
+        self.items = items;
+
+        // the restored first item only takes the marker if nothing upcoming holds it
+        self.items[0].state = if self.has_addhere() { First } else { AddHere };
+
+        // history always came before the upcoming items in the unshuffled order
+        if let Some(shuffle) = &mut self.shuffle {
+            for i in shuffle.iter_mut() {
+                *i += restored;
+            }
+            shuffle.splice(0..0, 0..restored);
+        }
+    }
+
+    pub fn move_to(&mut self, index: usize) -> Result<(), QueueError> {
+        self.generation += 1;
+        if self.items.is_empty() {
+            return Err(QueueError::EmptyQueue);
+        }
+        if index >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        if index == 0 {
+            return self.current().map(|_| ());
+        }
+
+        self.advance(index);
+        self.pass_errored();
+        debug_assert!(self.validate().is_ok());
+        self.current().map(|_| ())
+    }
+
+    /// Advances to the first item equal to `target`, moving everything before it to `played`
+    pub fn jump_to_by_value(&mut self, target: &QueueItemType<T, U>) -> Result<(), QueueError> {
+        use QueueState::*;
+
+        self.generation += 1;
+        let Some(position) = self.position_of(target) else {
+            return Err(QueueError::NotFound);
+        };
+        self.advance(position);
+
+        for item in &mut self.items {
+            if item.state == AddHere {
+                item.state = NoState;
+            }
+        }
+        self.items[0].state = AddHere;
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Moves the first `count` items to `played`, passing the AddHere marker along
+    fn advance(&mut self, count: usize) {
+        use QueueState::*;
+
+        for _ in 0..count {
+            let passed = self.items[0].state == AddHere && self.items.get(1).is_some();
+            if passed {
+                self.items[1].state = AddHere;
+            }
+            let mut item = self.items.remove(0);
+            self.shuffle_removed(0);
+            item.state = Played;
+            let run = self.addhere_passed;
+            self.push_played(item, true);
+            if passed {
+                self.addhere_passed = run + 1;
+            }
+        }
+    }
+
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.generation += 1;
+        self.swap_items(a, b);
+        debug_assert!(self.validate().is_ok());
+    }
+
+    fn swap_items(&mut self, a: usize, b: usize) {
+        self.items.swap(a, b);
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.swap(a, b);
+        }
+    }
+
+    /// Moves the item at `from` to `to`, keeping its state. Moving an item onto its own index
+    /// does nothing
+    pub fn move_item(&mut self, from: usize, to: usize) {
+        self.generation += 1;
+        if from == to {
+            return;
+        }
+        let item = self.items.remove(from);
+        self.items.insert(to, item);
+
+        if let Some(shuffle) = &mut self.shuffle {
+            let original = shuffle.remove(from);
+            shuffle.insert(to, original);
+        }
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Moves every item in `indices` so they sit together at `to`, keeping their order.
+    ///
+    /// `to` is an index from before the move, so the items end up where `items[to]` was. Nothing
+    /// is moved if any index is out of bounds or repeated
+    pub fn move_items(&mut self, indices: &[usize], to: usize) -> Result<(), QueueError> {
+        self.generation += 1;
+        let len = self.items.len();
+        let mut sorted = indices.to_vec();
+        sorted.sort_unstable();
+
+        if let Some(&index) = sorted.iter().find(|&&i| i >= len) {
+            return Err(QueueError::OutOfBounds { index, len });
+        }
+        if to > len {
+            return Err(QueueError::OutOfBounds { index: to, len });
+        }
+        if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(QueueError::DuplicateIndex { index: pair[0] });
+        }
+
+        let mut moved = Vec::with_capacity(sorted.len());
+        let mut originals = Vec::with_capacity(sorted.len());
+        for &i in sorted.iter().rev() {
+            moved.push(self.items.remove(i));
+            if let Some(shuffle) = &mut self.shuffle {
+                originals.push(shuffle.remove(i));
+            }
+        }
+        moved.reverse();
+        originals.reverse();
+
+        let to = to - sorted.iter().filter(|&&i| i < to).count();
+        self.items.splice(to..to, moved);
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle.splice(to..to, originals);
+        }
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Moves an item to play right after the current item
+    pub fn promote(&mut self, index: usize) -> Result<(), QueueError> {
+        use QueueState::*;
+
+        self.generation += 1;
+        if index >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        if index <= 1 {
+            return Ok(());
+        }
+
+        let mut item = self.items.remove(index);
+        // the marker stays in place, on whatever came before the promoted item
+        if item.state == AddHere {
+            self.items[index - 1].state = AddHere;
+            item.state = NoState;
+        }
+        self.items.insert(1, item);
+
+        if let Some(shuffle) = &mut self.shuffle {
+            let original = shuffle.remove(index);
+            shuffle.insert(1, original);
+        }
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Moves an item one place towards the front, returning its new index.
+    ///
+    /// The current item is only displaced if `displace_current` is set
+    pub fn move_up(&mut self, index: usize, displace_current: bool) -> Result<usize, QueueError> {
+        self.generation += 1;
+        if index >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        if index == 0 || index == 1 && !displace_current {
+            return Ok(index);
+        }
+        self.swap_items(index, index - 1);
+        debug_assert!(self.validate().is_ok());
+        Ok(index - 1)
+    }
+
+    /// Moves an item one place towards the back, returning its new index.
+    ///
+    /// The current item is only moved if `displace_current` is set
+    pub fn move_down(&mut self, index: usize, displace_current: bool) -> Result<usize, QueueError> {
+        self.generation += 1;
+        if index >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        if index == self.items.len() - 1 || index == 0 && !displace_current {
+            return Ok(index);
+        }
+        self.swap_items(index, index + 1);
+        debug_assert!(self.validate().is_ok());
+        Ok(index + 1)
+    }
+
+    /// Swaps `items[index]` with the item after it
+    pub fn swap_adjacent(&mut self, index: usize) -> Result<(), QueueError> {
+        self.generation += 1;
+        if index + 1 >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        self.swap_items(index, index + 1);
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// Swaps `items[index]` with the item before it, failing on the first item
+    pub fn move_item_up(&mut self, index: usize) -> Result<(), QueueError> {
+        match index.checked_sub(1) {
+            Some(before) if index < self.items.len() => self.swap_adjacent(before),
+            _ => {
+                self.generation += 1;
+                Err(QueueError::OutOfBounds {
+                    index,
+                    len: self.items.len(),
+                })
+            }
+        }
+    }
+
+    /// Swaps `items[index]` with the item after it, failing on the last item
+    pub fn move_item_down(&mut self, index: usize) -> Result<(), QueueError> {
+        self.swap_adjacent(index)
+    }
+
+    /// The same as [`Queue::move_item_down`], the item keeps its state, AddHere included
+    pub fn swap_with_next(&mut self, index: usize) -> Result<(), QueueError> {
+        self.move_item_down(index)
+    }
+
+    /// The same as [`Queue::move_item_up`], the item keeps its state, AddHere included
+    pub fn swap_with_prev(&mut self, index: usize) -> Result<(), QueueError> {
+        self.move_item_up(index)
+    }
+
+    /// Stably sorts the items by a key, optionally leaving the current item in place
+    pub fn sort_upcoming_by<K: Ord, F: FnMut(&QueueItemType<T, U>) -> K>(
+        &mut self,
+        keep_current: bool,
+        mut f: F,
+    ) {
+        self.generation += 1;
+        let start = if keep_current { 1 } else { 0 }.min(self.items.len());
+        self.items[start..].sort_by_key(|item| f(&item.item));
+        self.shuffle = None;
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Like [`Queue::sort_upcoming_by`], but only computes the key once per item
+    pub fn sort_upcoming_by_cached_key<K: Ord, F: FnMut(&QueueItemType<T, U>) -> K>(
+        &mut self,
+        keep_current: bool,
+        mut f: F,
+    ) {
+        self.generation += 1;
+        let start = if keep_current { 1 } else { 0 }.min(self.items.len());
+        self.items[start..].sort_by_cached_key(|item| f(&item.item));
+        self.shuffle = None;
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Reverses the order of the items, optionally leaving the current item in place
+    pub fn reverse_upcoming(&mut self, keep_current: bool) {
+        self.generation += 1;
+        let start = if keep_current { 1 } else { 0 }.min(self.items.len());
+        self.items[start..].reverse();
+        if let Some(shuffle) = &mut self.shuffle {
+            shuffle[start..].reverse();
+        }
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Stable sorts the items after the current one by priority, highest first.
+    ///
+    /// The AddHere marker stays at the same index rather than moving with its item
+    pub fn sort_by_priority(&mut self) {
+        use QueueState::*;
+
+        self.generation += 1;
+        if self.items.len() < 2 {
+            return;
+        }
+
+        let addhere = self.addhere_index();
+        if let Some(i) = addhere {
+            self.items[i].state = NoState;
+        }
+        let mut upcoming: Vec<_> = self.items.drain(1..).enumerate().collect();
+        upcoming.sort_by(|(_, a), (_, b)| a.cmp_priority(b));
+        if let Some(shuffle) = &mut self.shuffle {
+            let old = shuffle.split_off(1);
+            shuffle.extend(upcoming.iter().map(|&(i, _)| old[i]));
+        }
+        self.items.extend(upcoming.into_iter().map(|(_, item)| item));
+        if let Some(i) = addhere {
+            self.items[i].state = AddHere;
+        }
+        self.addhere_hint = addhere;
+        debug_assert!(self.validate().is_ok());
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<&QueueItem<T, U>, QueueError> {
+        self.generation += 1;
+        // an errored item can't be repeated, so it gets passed over like any other
+        if self.repeat_one && !self.items.first().is_some_and(|item| item.errored) {
+            let current = self.current()?;
+            let mut item = current.clone();
+            item.state = QueueState::Played;
+            // a repeat doesn't move AddHere, and prev takes it back off without restoring anything
+            let run = self.addhere_passed;
+            self.push_played(item, false);
+            self.addhere_passed = run;
+            self.stats.total_played += 1;
+            return Ok(&self.items[0]);
+        }
+
+        self.advance_current(false)
+    }
+
+    /// Moves on like [`Queue::next`], but marks the current item Skipped in `played`.
+    ///
+    /// Repeat one doesn't hold back a skip, it always goes on to the next item
+    pub fn skip(&mut self) -> Result<&QueueItem<T, U>, QueueError> {
+        self.generation += 1;
+        self.advance_current(true)
+    }
+
+    fn advance_current(&mut self, skipped: bool) -> Result<&QueueItem<T, U>, QueueError> {
+        if self.items.is_empty() {
+            if self.try_loop() {
+                self.pass_errored();
+                return self.current();
+            } else {
+                return Err(QueueError::EmptyQueue);
+            }
+        }
+
+        // a Multi item plays through all of its tracks before moving on
+        let errored = self.items[0].errored;
+        if !errored && self.items[0].track + 1 < self.items[0].item.track_count() {
+            self.items[0].track += 1;
+            if skipped {
+                self.stats.total_skipped += 1;
+            } else {
+                self.stats.total_played += 1;
+            }
+            return Ok(&self.items[0]);
+        }
+
+        self.pass_current(skipped || errored);
+        debug_assert!(self.validate().is_ok());
+
+        if self.items.is_empty() && !self.try_loop() {
+            Err(QueueError::NoNext)
+        } else {
+            self.pass_errored();
+            self.current()
+        }
+    }
+
+    /// Moves the whole current item to `played`, passing the AddHere marker along
+    fn pass_current(&mut self, skipped: bool) {
+        let mut passed = false;
+        if self.items[0].state == QueueState::AddHere || !self.has_addhere() {
+            self.items[0].state = QueueState::NoState;
+            if self.items.get_mut(1).is_some() {
+                self.items[1].state = QueueState::AddHere;
+                self.addhere_hint = Some(1);
+                passed = true;
+            }
+        }
+        self.addhere_hint = self.addhere_hint.and_then(|hint| hint.checked_sub(1));
+        let mut item = self.items.remove(0);
+        self.shuffle_removed(0);
+        if skipped {
+            item.state = QueueState::Skipped;
+            self.stats.total_skipped += 1;
+        } else {
+            item.state = QueueState::Played;
+            self.stats.total_played += 1;
+        }
+        let run = self.addhere_passed;
+        self.push_played(item, true);
+        if passed {
+            self.addhere_passed = run + 1;
+        }
+    }
+
+    /// Skips errored items at the front, unless nothing after them can be played either
+    fn pass_errored(&mut self) {
+        while self.items.first().is_some_and(|item| item.errored)
+            && self.items.iter().any(|item| !item.errored)
+        {
+            self.pass_current(true);
+        }
+    }
+
+    /// Marks the item at `index` as one that can't be played, or clears the mark.
+    ///
+    /// Errored items stay in the queue so they can still be shown, but [`Queue::next`],
+    /// [`Queue::skip`] and [`Queue::move_to`] pass over them into `played` as Skipped, and
+    /// [`Queue::prev`] goes back over them. Marking the current item moves on straight away if
+    /// there is anything else to play
+    pub fn set_item_error(&mut self, index: usize, errored: bool) -> Result<(), QueueError> {
+        self.generation += 1;
+        let len = self.items.len();
+        let Some(item) = self.items.get_mut(index) else {
+            return Err(QueueError::OutOfBounds { index, len });
+        };
+        item.errored = errored;
+        self.pass_errored();
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// The indices of the upcoming items that are errored
+    pub fn errored_indices(&self) -> Vec<usize> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.errored)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn prev(&mut self) -> Result<&QueueItem<T, U>, QueueError> {
+        self.generation += 1;
+        self.step_back()?;
+        debug_assert!(self.validate().is_ok());
+        Ok(&self.items[0])
+    }
+
+    /// Goes back one step for [`Queue::prev`] and [`Queue::prev_n`]
+    fn step_back(&mut self) -> Result<(), QueueError> {
+        // going back over a repeat of the current item just stays on it
+        let repeated = match (self.items.first(), self.played.last()) {
+            (Some(current), Some(last)) => !current.errored && current.item == last.item,
+            _ => false,
+        };
+        if self.repeat_one && repeated {
+            let repeat = self.played.pop().unwrap();
+            self.bury(repeat);
+            return Ok(());
+        }
+
+        // a Multi item goes back through its own tracks first
+        let current = self.items.first_mut().filter(|item| !item.errored && item.track > 0);
+        if let Some(current) = current {
+            current.track -= 1;
+            return Ok(());
+        }
+
+        // errored items are gone back over too, they come back as upcoming after the new current
+        let Some(back) = self.played.iter().rposition(|item| !item.errored) else {
+            return Err(QueueError::EmptyPlayed);
+        };
+        let run = self.addhere_passed.min(self.played.len());
+        let mut restored: Vec<_> = self.played.drain(back..).collect();
+        // whatever state and time they had when they were played is stale by now, but a Multi
+        // item picks up on the last track it played
+        for item in &mut restored {
+            let track = item.track;
+            item.requeue();
+            item.track = track;
+        }
+        // hand AddHere back to whichever restored item passed it on to the current one, and with
+        // no marker at all the restored item takes one to add after
+        let count = restored.len();
+        let addhere = match self.addhere_index() {
+            Some(0) if run > 0 => {
+                self.items[0].state = QueueState::NoState;
+                Some(count.saturating_sub(run))
+            }
+            None => Some(0),
+            _ => None,
+        };
+        if let Some(i) = addhere {
+            restored[i].state = QueueState::AddHere;
+        }
+        self.addhere_passed = run.saturating_sub(count);
+        self.splice_items(0, restored);
+        if addhere.is_some() {
+            self.addhere_hint = addhere;
+        }
+        Ok(())
+    }
+
+    /// Goes back `n` times like [`Queue::prev`], returning the new current item.
+    ///
+    /// If there isn't enough history to go back `n` times this fails with
+    /// [`QueueError::EmptyPlayed`] and leaves the queue as it was
+    pub fn prev_n(&mut self, n: usize) -> Result<&QueueItem<T, U>, QueueError> {
+        self.generation += 1;
+        if n == 0 {
+            return self.current();
+        }
+        if self.prev_steps(n) < n {
+            return Err(QueueError::EmptyPlayed);
+        }
+        for _ in 0..n {
+            self.step_back()?;
+        }
+        debug_assert!(self.validate().is_ok());
+        Ok(&self.items[0])
+    }
+
+    /// How many of the next `n` calls to [`Queue::prev`] would succeed
+    fn prev_steps(&self, n: usize) -> usize {
+        // an errored current item is gone back over the same as an empty queue
+        let mut current = self
+            .items
+            .first()
+            .filter(|item| !item.errored)
+            .map(|item| (&item.item, item.track));
+        let mut played = self.played.len();
+        for step in 0..n {
+            let last = played.checked_sub(1).map(|last| &self.played[last]);
+            match (&mut current, last) {
+                (Some((item, _)), Some(last)) if self.repeat_one && **item == last.item => {
+                    played -= 1;
+                }
+                (Some((_, track)), _) if *track > 0 => *track -= 1,
+                _ => {
+                    let history = &self.played[..played];
+                    let Some(back) = history.iter().rposition(|item| !item.errored) else {
+                        return step;
+                    };
+                    played = back;
+                    current = Some((&history[back].item, history[back].track));
+                }
+            }
+        }
+        n
+    }
+
+    /// The track of the current Multi item that is playing, or `None` if the current item is a
+    /// Single
+    pub fn current_track_in_group(&self) -> Option<T>
+    where
+        U: IntoIterator<Item = T>,
+    {
+        match &self.items.first()?.item {
+            QueueItemType::Multi(tracks) => {
+                tracks.clone().into_iter().nth(self.items[0].track)
+            }
+            QueueItemType::Single(_) => None,
+        }
+    }
+
+    /// Returns what the next `n` calls to [`Queue::next`] would, along with the track each one
+    /// leaves the item on, without changing anything.
+    ///
+    /// It stops early where `next` would fail, and repeats a Multi item once for each of its
+    /// tracks left to play, the same way `next` does
+    pub fn next_n(&self, n: usize) -> Vec<(&QueueItem<T, U>, usize)> {
+        let mut steps = Vec::with_capacity(n);
+        let mut upcoming: Vec<&QueueItem<T, U>> = self.items.iter().collect();
+        // along with whether each one is part of the cycle, like `ItemTag::cycle`
+        let mut played: Vec<(&QueueItem<T, U>, bool)> =
+            self.played.iter().map(|item| (item, item.tag.cycle)).collect();
+        let mut pos = 0;
+        let mut track = upcoming.first().map_or(0, |item| item.track);
+        // items coming back from `played` start over from their first track
+        let mut restarted = false;
+        let mut loop_ = self.loop_;
+        let mut rng = self.rng.clone();
+
+        // an errored current item is passed over, and whatever comes after it repeats instead
+        if self.repeat_one {
+            match upcoming.first() {
+                Some(current) if current.errored => (),
+                Some(current) => {
+                    steps.resize(n, (*current, current.track));
+                    return steps;
+                }
+                None => return steps,
+            }
+        }
+
+        while steps.len() < n {
+            if let Some(&current) = upcoming.get(pos) {
+                if !current.errored && track + 1 < current.item.track_count() {
+                    track += 1;
+                    steps.push((current, track));
+                    continue;
+                }
+
+                self.push_predicted(&mut played, current);
+                pos += 1;
+            }
+
+            if pos >= upcoming.len() {
+                // the same as try_loop
+                if played.is_empty() {
+                    break;
+                }
+                match loop_ {
+                    LoopSetting::Off | LoopSetting::Times(0) => break,
+                    LoopSetting::Times(n) => loop_ = LoopSetting::Times(n - 1),
+                    LoopSetting::Forever => (),
+                }
+                let cycle: Vec<_> =
+                    played.iter().filter(|(_, cycle)| *cycle).map(|&(item, _)| item).collect();
+                if cycle.is_empty() {
+                    upcoming = mem::take(&mut played).into_iter().map(|(item, _)| item).collect();
+                } else if self.shuffle.is_some() {
+                    let order = Self::cycle_order(&mut rng, &cycle);
+                    upcoming = order.iter().map(|&i| cycle[i]).collect();
+                    for (_, cycle) in &mut played {
+                        *cycle = false;
+                    }
+                } else {
+                    upcoming = cycle;
+                    played.clear();
+                }
+                pos = 0;
+                restarted = true;
+            }
+
+            // the same as pass_errored, and next fails if nothing left can be played
+            if !upcoming[pos..].iter().any(|item| !item.errored) {
+                break;
+            }
+            while upcoming[pos].errored {
+                self.push_predicted(&mut played, upcoming[pos]);
+                pos += 1;
+            }
+            let next = upcoming[pos];
+            track = if restarted { 0 } else { next.track };
+            steps.push((next, track));
+            if self.repeat_one {
+                steps.resize(n, (next, track));
+            }
+        }
+        steps
+    }
+
+    /// The same as [`Queue::push_played`], for the copy of `played` in [`Queue::next_n`]
+    fn push_predicted<'a>(
+        &self,
+        played: &mut Vec<(&'a QueueItem<T, U>, bool)>,
+        item: &'a QueueItem<T, U>,
+    ) {
+        if self.history_dedup {
+            played.retain(|(played, _)| played.item != item.item);
+        }
+        played.push((item, true));
+        if let Some(limit) = self.history_limit {
+            if played.len() > limit {
+                played.drain(..played.len() - limit);
+            }
+        }
+    }
+
+    pub fn current(&self) -> Result<&QueueItem<T, U>, QueueError> {
+        match self.items.first() {
+            // an errored item can end up current when something other than moving on put it
+            // there, but playback is only stuck once nothing after it can be played either
+            Some(current) if current.errored && self.items.iter().all(|item| item.errored) => {
+                Err(QueueError::AllErrored)
+            }
+            Some(current) => Ok(current),
+            None => Err(QueueError::EmptyQueue),
+        }
+    }
+
+    /// Counts the tracks that have not been played yet, with every item of a Multi counting
+    pub fn total_item_count(&self) -> usize {
+        self.items.iter().map(|item| item.item.track_count()).sum()
+    }
+
+    /// Counts the items that have not been played yet
+    pub fn item_stats(&self) -> ItemStats {
+        ItemStats::count(self.items.iter())
+    }
+
+    /// Counts the items that have been played
+    pub fn played_item_stats(&self) -> ItemStats {
+        ItemStats::count(self.played.iter())
+    }
+
+    /// Sets `by_human` on every item in `items[start..end]`
+    pub fn set_by_human_for_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.generation += 1;
+        if start > end || end > self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index: start.max(end),
+                len: self.items.len(),
+            });
+        }
+        for item in &mut self.items[start..end] {
+            item.by_human = by_human;
+        }
+        Ok(())
+    }
+
+    /// Replaces the value of every upcoming Single item with `f` applied to it
+    pub fn map_items<F: Fn(T) -> T>(&mut self, f: F) {
+        self.generation += 1;
+        for mut item in mem::take(&mut self.items) {
+            if let QueueItemType::Single(_) = item.item {
+                // the old value goes, so the item counts as a new one to a transaction
+                self.bury_copy(&item);
+                item.tag.key = 0;
+            }
+            item.item = match item.item {
+                QueueItemType::Single(single) => QueueItemType::Single(f(single)),
+                multi => multi,
+            };
+            self.items.push(item);
+        }
+    }
+
+    /// Replaces every upcoming item with the result of `f`, removing it if that is `None`.
+    ///
+    /// Like [`Queue::remove_item`], a removed AddHere marker is passed on to the next item kept, or
+    /// the last one if none come after it. A removed First marker goes to the first item, unless
+    /// that one has a marker of its own
+    pub fn filter_map_items<F>(&mut self, f: F)
+    where
+        F: Fn(QueueItemType<T, U>) -> Option<QueueItemType<T, U>>,
+    {
+        use QueueState::*;
+
+        self.generation += 1;
+        let mut removed = Vec::new();
+        let mut lost_first = false;
+        let mut lost_addhere = None;
+        let mut items = Vec::with_capacity(self.items.len());
+
+        for (i, mut item) in mem::take(&mut self.items).into_iter().enumerate() {
+            // the old value goes, so the item counts as a new one to a transaction
+            self.bury_copy(&item);
+            item.tag.key = 0;
+            match f(item.item) {
+                Some(new) => items.push(QueueItem {
+                    // a shorter Multi can't be further along than its last track
+                    track: item.track.min(new.track_count().saturating_sub(1)),
+                    item: new,
+                    ..item
+                }),
+                None => {
+                    match item.state {
+                        First => lost_first = true,
+                        AddHere => lost_addhere = Some(items.len()),
+                        _ => (),
+                    }
+                    removed.push(i);
+                }
+            }
+        }
+        self.items = items;
+
+        // the item that took the place of the AddHere item takes the marker, or the last one
+        if let Some(at) = lost_addhere.filter(|_| !self.items.is_empty()) {
+            let at = at.min(self.items.len() - 1);
+            self.items[at].state = AddHere;
+            self.addhere_hint = Some(at);
+        }
+        if lost_first {
+            if let Some(first) = self.items.first_mut().filter(|item| item.state == NoState) {
+                first.state = First;
+            }
+        }
+
+        for i in removed.into_iter().rev() {
+            self.shuffle_removed(i);
+        }
+        debug_assert!(self.validate().is_ok());
+    }
+
+    /// Replaces the Multi item at `index` with its tracks as Single items, returning how many
+    /// items it became.
+    ///
+    /// The tracks keep the group's `by_human`, priority, meta and errored flag, the first one takes
+    /// a First marker and the last one an AddHere marker. Tracks before the one the group is on
+    /// have already played, so they go into `played` and the current track stays current. A
+    /// Single item is left as it is
+    pub fn flatten(&mut self, index: usize) -> Result<usize, QueueError>
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.generation += 1;
+        if index >= self.items.len() {
+            return Err(QueueError::OutOfBounds {
+                index,
+                len: self.items.len(),
+            });
+        }
+        let count = self.flatten_item(index);
+        debug_assert!(self.validate().is_ok());
+        Ok(count)
+    }
+
+    /// Flattens every Multi item like [`Queue::flatten`], returning how many there were
+    pub fn flatten_all(&mut self) -> usize
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.generation += 1;
+        let mut flattened = 0;
+        for index in (0..self.items.len()).rev() {
+            if let QueueItemType::Multi(_) = self.items[index].item {
+                self.flatten_item(index);
+                flattened += 1;
+            }
+        }
+        debug_assert!(self.validate().is_ok());
+        flattened
+    }
+
+    fn flatten_item(&mut self, index: usize) -> usize
+    where
+        U: IntoIterator<Item = T>,
+    {
+        use QueueState::*;
+
+        if let QueueItemType::Single(_) = self.items[index].item {
+            return 1;
+        }
+        let group = self.items.remove(index);
+        self.bury_copy(&group);
+        let QueueItemType::Multi(tracks) = group.item else {
+            unreachable!();
+        };
+        let mut tracks = tracks.into_iter().map(|track| QueueItem {
+            meta: group.meta.clone(),
+            errored: group.errored,
+            priority: group.priority,
+            ..QueueItem::from_item_type(QueueItemType::Single(track), group.by_human)
+        });
+        for played in tracks.by_ref().take(group.track) {
+            self.push_played(played.with_state(Played), true);
+        }
+        let mut tracks: Vec<QueueItem<T, U>> = tracks.collect();
+        let count = tracks.len();
+
+        match group.state {
+            AddHere => match tracks.last_mut() {
+                Some(last) => last.state = AddHere,
+                // nothing to hold the marker, so it goes back a place like in dedup
+                None => {
+                    if let Some(prev) = index.checked_sub(1).and_then(|i| self.items.get_mut(i)) {
+                        prev.state = AddHere;
+                    } else if let Some(next) = self.items.get_mut(index) {
+                        next.state = AddHere;
+                    }
+                }
+            },
+            First => {
+                if let Some(first) = tracks.first_mut() {
+                    first.state = First;
+                }
+            }
+            _ => (),
+        }
+        self.items.splice(index..index, tracks);
+
+        // the tracks take the group's place in the unshuffled order too
+        if count == 0 {
+            self.shuffle_removed(index);
+        } else if let Some(shuffle) = self.shuffle.as_mut().filter(|order| index < order.len()) {
+            let original = shuffle[index];
+            for i in shuffle.iter_mut() {
+                if *i > original {
+                    *i += count - 1;
+                }
+            }
+            shuffle.splice(index..=index, original..original + count);
+        }
+        count
+    }
+
+    /// Returns a copy of the upcoming items with `f` applied to every Single item
+    pub fn mapped_items<F: Fn(&T) -> T>(&self, f: F) -> Vec<QueueItem<T, U>> {
+        self.items
+            .iter()
+            .map(|item| match &item.item {
+                QueueItemType::Single(single) => QueueItem {
+                    track: item.track,
+                    meta: item.meta.clone(),
+                    errored: item.errored,
+                    ..QueueItem::from_item_type(QueueItemType::Single(f(single)), item.by_human)
+                        .with_state(item.state)
+                        .with_priority(item.priority)
+                },
+                QueueItemType::Multi(_) => item.clone(),
+            })
+            .collect()
+    }
+
+    /// Returns the index of the first upcoming item equal to `item`
+    pub fn position_of(&self, item: &QueueItemType<T, U>) -> Option<usize> {
+        self.items.iter().position(|queued| queued.item == *item)
+    }
+
+    /// Returns the index of every upcoming item equal to `item`
+    pub fn positions_of(&self, item: &QueueItemType<T, U>) -> Vec<usize> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter(|(_, queued)| queued.item == *item)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the index of the first upcoming item `pred` is true for
+    pub fn position_where<F: Fn(&QueueItem<T, U>) -> bool>(&self, pred: F) -> Option<usize> {
+        self.items.iter().position(pred)
+    }
+
+    /// Returns the index in `played` of the first item `pred` is true for
+    pub fn position_where_in_history<F>(&self, pred: F) -> Option<usize>
+    where
+        F: Fn(&QueueItem<T, U>) -> bool,
+    {
+        self.played.iter().position(pred)
+    }
+
+    /// Returns the first upcoming item `pred` is true for
+    pub fn find_where<F: Fn(&QueueItem<T, U>) -> bool>(&self, pred: F) -> Option<&QueueItem<T, U>> {
+        self.items.iter().find(|item| pred(item))
+    }
+
+    /// Returns the first item in `played` that `pred` is true for
+    pub fn find_where_in_history<F>(&self, pred: F) -> Option<&QueueItem<T, U>>
+    where
+        F: Fn(&QueueItem<T, U>) -> bool,
+    {
+        self.played.iter().find(|item| pred(item))
+    }
+
+    /// Whether `single` is upcoming, on its own or as part of a Multi item
+    pub fn contains_single(&self, single: &T) -> bool
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.items.iter().any(|item| item.item.contains_single(single))
+    }
+
+    /// Whether `single` has been played, on its own or as part of a Multi item
+    pub fn contains_single_in_history(&self, single: &T) -> bool
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.played.iter().any(|item| item.item.contains_single(single))
+    }
+
+    /// Works out the steps that turn `previous` into the current items.
+    ///
+    /// Items are matched by their value and `by_human`, so the result is not always the
+    /// shortest possible, but applying it to `previous` always gives the current items
+    pub fn diff(&self, previous: &[QueueItem<T, U>]) -> Vec<QueueDelta> {
+        let same = |a: &QueueItem<T, U>, b: &QueueItem<T, U>| {
+            a.item == b.item && a.by_human == b.by_human
+        };
+
+        // pair every old item with a current one, trying the same position first
+        let mut taken = vec![false; self.items.len()];
+        let matched: Vec<Option<usize>> = previous
+            .iter()
+            .enumerate()
+            .map(|(i, old)| {
+                let j = if self.items.get(i).is_some_and(|new| !taken[i] && same(old, new)) {
+                    Some(i)
+                } else {
+                    (0..self.items.len()).find(|&j| !taken[j] && same(old, &self.items[j]))
+                };
+                if let Some(j) = j {
+                    taken[j] = true;
+                }
+                j
+            })
+            .collect();
+
+        // removing from the back keeps the indices of earlier removals valid
+        let mut deltas: Vec<QueueDelta> = (0..previous.len())
+            .rev()
+            .filter(|&i| matched[i].is_none())
+            .map(|index| QueueDelta::Removed { index })
+            .collect();
+
+        // everything before `index` is already in place, so later steps never disturb it
+        let mut working: Vec<Option<usize>> =
+            matched.iter().copied().filter(Option::is_some).collect();
+        for (index, &found) in taken.iter().enumerate() {
+            if !found {
+                working.insert(index, None);
+                deltas.push(QueueDelta::Inserted { index });
+                continue;
+            }
+            let from = working.iter().position(|&j| j == Some(index)).unwrap();
+            if from != index {
+                let j = working.remove(from);
+                working.insert(index, j);
+                deltas.push(QueueDelta::Moved { from, to: index });
+            }
+        }
+
+        let mut changed: Vec<usize> = matched
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| j.filter(|&j| previous[i] != self.items[j]))
+            .collect();
+        changed.sort_unstable();
+        deltas.extend(changed.into_iter().map(|index| QueueDelta::StateChanged { index }));
+
+        deltas
+    }
+
+    /// Sums up how the upcoming items differ from those of `previous`.
+    ///
+    /// Items are matched the same way as in [`Queue::diff`], use that for the exact steps
+    pub fn diff_summary(&self, previous: &Queue<T, U>) -> QueueDiff<T, U> {
+        let same = |a: &QueueItem<T, U>, b: &QueueItem<T, U>| {
+            a.item == b.item && a.by_human == b.by_human
+        };
+
+        let mut taken = vec![false; self.items.len()];
+        let mut removed = Vec::new();
+        let mut last = None;
+        let mut reordered = false;
+        for old in &previous.items {
+            let found = self
+                .items
+                .iter()
+                .zip(&taken)
+                .position(|(new, &taken)| !taken && same(old, new));
+            match found {
+                Some(i) => {
+                    taken[i] = true;
+                    reordered |= last.is_some_and(|last| i < last);
+                    last = Some(i);
+                }
+                None => removed.push(old.clone()),
+            }
+        }
+
+        let added = self
+            .items
+            .iter()
+            .zip(&taken)
+            .filter(|(_, &taken)| !taken)
+            .map(|(item, _)| item.clone())
+            .collect();
+        QueueDiff {
+            added,
+            removed,
+            reordered,
+        }
+    }
+
+    /// Returns a copy of every item that has not been played yet
+    pub fn clone_remaining(&self) -> Vec<QueueItem<T, U>> {
+        self.items.clone()
+    }
+
+    pub fn checkpoint(&self) -> QueueSnapshot<T, U> {
+        QueueSnapshot {
+            items: self.items.clone(),
+            played: self.played.clone(),
+            loop_: self.loop_,
+            repeat_one: self.repeat_one,
+            shuffle: self.shuffle.clone(),
+            shuffle_mode: self.shuffle_mode,
+            history_limit: self.history_limit,
+            history_dedup: self.history_dedup,
+            max_len: self.max_len,
+            eviction_policy: self.eviction_policy,
+            stats: self.stats,
+            loop_times: self.loop_times,
+            rng: self.rng.clone(),
+            addhere_passed: self.addhere_passed,
+        }
+    }
+
+    /// Replaces the whole state of the queue with a snapshot, leaving open transactions open
+    pub fn restore(&mut self, snapshot: QueueSnapshot<T, U>) {
+        self.generation += 1;
+        let old = mem::replace(&mut self.items, snapshot.items);
+        self.bury_all(old);
+        let old = mem::replace(&mut self.played, snapshot.played);
+        self.bury_all(old);
+        // the snapshot could be from another transaction, with its keys meaning other values
+        for item in self.items.iter_mut().chain(&mut self.played) {
+            item.tag.key = 0;
+        }
+        self.loop_ = snapshot.loop_;
+        self.loop_times = snapshot.loop_times;
+        self.repeat_one = snapshot.repeat_one;
+        self.shuffle = snapshot.shuffle;
+        self.shuffle_mode = snapshot.shuffle_mode;
+        self.history_limit = snapshot.history_limit;
+        self.history_dedup = snapshot.history_dedup;
+        self.max_len = snapshot.max_len;
+        self.eviction_policy = snapshot.eviction_policy;
+        self.stats = snapshot.stats;
+        self.rng = snapshot.rng;
+        self.addhere_passed = snapshot.addhere_passed;
+        self.addhere_hint = None;
+        debug_assert!(self.validate().is_ok());
+    }
+
+    pub fn check_played(&mut self, limit: usize) {
+        self.generation += 1;
+        if let Some(over) = self.played.len().checked_sub(limit) {
+            let dropped: Vec<_> = self.played.drain(..over).collect();
+            self.bury_all(dropped);
+        }
+    }
+
+    /// Keeps only the latest play of each item in `played`, so the history reads like a list of
+    /// recently played items instead of growing with every repeat
+    pub fn set_history_dedup(&mut self, dedup: bool) {
+        self.generation += 1;
+        self.history_dedup = dedup;
+        if dedup {
+            let mut i = self.played.len();
+            while i > 0 {
+                i -= 1;
+                if self.played[i + 1..].iter().any(|item| item.item == self.played[i].item) {
+                    let item = self.played.remove(i);
+                    self.bury(item);
+                }
+            }
+        }
+    }
+
+    /// Adds an item to the history, dropping the oldest ones past the history limit.
+    ///
+    /// `in_cycle` is whether the item left `items` to get there, rather than being a copy
+    fn push_played(&mut self, mut item: QueueItem<T, U>, in_cycle: bool) {
+        item.tag.cycle = in_cycle;
+        #[cfg(feature = "std")]
+        let item = QueueItem {
+            played_at: Some(SystemTime::now()),
+            ..item
+        };
+        if self.history_dedup {
+            let (others, repeats) = mem::take(&mut self.played)
+                .into_iter()
+                .partition(|played| played.item != item.item);
+            self.played = others;
+            self.bury_all(repeats);
+        }
+        self.played.push(item);
+        // the callers that pass AddHere along count themselves back in
+        self.addhere_passed = 0;
+        self.trim_history();
+    }
+}
+
+/// Something with a known playback length, used by [`Queue::remaining_duration`]
+pub trait HasDuration {
+    fn duration(&self) -> Duration;
+}
+
+impl<T: HasDuration, U: HasDuration> Queue<T, U> {
+    fn sum_durations(items: &[QueueItem<T, U>]) -> Duration {
+        items
+            .iter()
+            .map(|item| match &item.item {
+                QueueItemType::Single(item) => item.duration(),
+                QueueItemType::Multi(items) => items.duration(),
+            })
+            .sum()
+    }
+
+    /// The total length of every item that has not been played yet, including the current one
+    pub fn remaining_duration(&self) -> Duration {
+        Self::sum_durations(&self.items)
+    }
+
+    /// The total length of every played item
+    pub fn played_duration(&self) -> Duration {
+        Self::sum_durations(&self.played)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum QueueError {
+    OutOfBounds { index: usize, len: usize },
+    EmptyQueue,
+    EmptyPlayed,
+    NoNext,
+    NotFound,
+    DuplicateIndex { index: usize },
+    Full,
+    /// Every upcoming item is errored, so there is nothing that can be played
+    AllErrored,
+    /// A transaction was ended without one being open
+    NoTransaction,
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueError::OutOfBounds { index, len } => {
+                write!(f, "Index out of bounds! Index {index} is over len {len}")
+            }
+            QueueError::EmptyQueue => write!(f, "The Queue is empty!"),
+            QueueError::EmptyPlayed => write!(f, "There are no past played songs!"),
+            QueueError::NoNext => write!(f, "There is no item after this in the Queue"),
+            QueueError::NotFound => write!(f, "The item is not in the Queue"),
+            QueueError::DuplicateIndex { index } => {
+                write!(f, "Index {index} was given more than once")
+            }
+            QueueError::Full => write!(f, "The Queue is full"),
+            QueueError::AllErrored => write!(f, "None of the items left can be played"),
+            QueueError::NoTransaction => write!(f, "There is no transaction to end"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for QueueError {}
+
+/// An invariant [`Queue::validate`] found broken
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum QueueIntegrityError {
+    /// More than one item has a state only one item should have, AddHere or First
+    DuplicateState { state: QueueState, first: usize, second: usize },
+    /// An upcoming item is marked Played or Skipped
+    PlayedUpcoming { index: usize },
+    /// The shuffle order isn't the same length as `items`
+    ShuffleLength { len: usize, items: usize },
+    /// The shuffle order repeats an index or has one out of range at `index`
+    ShuffleNotPermutation { index: usize },
+}
+
+impl fmt::Display for QueueIntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueueIntegrityError::DuplicateState { state, first, second } => {
+                write!(f, "Items {first} and {second} are both {state:?}")
+            }
+            QueueIntegrityError::PlayedUpcoming { index } => {
+                write!(f, "Item {index} is marked as played but is still upcoming")
+            }
+            QueueIntegrityError::ShuffleLength { len, items } => {
+                write!(f, "The shuffle order has {len} entries but there are {items} items")
+            }
+            QueueIntegrityError::ShuffleNotPermutation { index } => {
+                write!(f, "Entry {index} of the shuffle order is repeated or out of range")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for QueueIntegrityError {}
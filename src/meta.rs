@@ -0,0 +1,64 @@
+use core::any::Any;
+use core::fmt::{self, Debug};
+
+use alloc::sync::Arc;
+
+use crate::QueueItem;
+
+/// App data attached to a [`QueueItem`], kept apart from the item itself.
+///
+/// Cloning it shares the same value, and it is ignored when comparing items, so two items that
+/// only differ in their metadata are still equal
+#[derive(Clone, Default)]
+pub struct ItemMeta(Option<Arc<dyn Any + Send + Sync>>);
+
+impl ItemMeta {
+    pub fn new<M: Any + Send + Sync>(meta: M) -> Self {
+        ItemMeta(Some(Arc::new(meta)))
+    }
+
+    /// The value, if there is one and it is an `M`
+    pub fn get<M: Any>(&self) -> Option<&M> {
+        self.0.as_deref()?.downcast_ref()
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+impl Debug for ItemMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("ItemMeta(..)"),
+            None => f.write_str("ItemMeta(None)"),
+        }
+    }
+}
+
+impl PartialEq for ItemMeta {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl<T, U> QueueItem<T, U> {
+    /// Attaches `meta` to the item, replacing whatever was there
+    pub fn with_meta<M: Any + Send + Sync>(mut self, meta: M) -> Self {
+        self.meta = ItemMeta::new(meta);
+        self
+    }
+
+    pub fn set_meta<M: Any + Send + Sync>(&mut self, meta: M) {
+        self.meta = ItemMeta::new(meta);
+    }
+
+    /// The metadata of the item, if it has some and it is an `M`
+    pub fn meta<M: Any>(&self) -> Option<&M> {
+        self.meta.get()
+    }
+
+    pub fn clear_meta(&mut self) {
+        self.meta = ItemMeta::default();
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+
+use crate::QueueEvent;
+
+/// How many events a subscriber can fall behind by before they are folded into one
+/// [`QueueEvent::Changed`]
+const INBOX_LEN: usize = 64;
+
+#[derive(Debug, Default)]
+struct Inbox {
+    events: VecDeque<QueueEvent>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sends every event to each live [`QueueSubscriber`], shared by all handles to a
+/// [`SharedQueue`](crate::SharedQueue)
+#[derive(Debug, Default)]
+pub(crate) struct Notifier {
+    inboxes: Mutex<Vec<Weak<Mutex<Inbox>>>>,
+}
+
+impl Notifier {
+    pub(crate) fn subscribe(&self) -> QueueSubscriber {
+        let inbox = Arc::new(Mutex::new(Inbox::default()));
+        lock(&self.inboxes).push(Arc::downgrade(&inbox));
+        QueueSubscriber { inbox }
+    }
+
+    /// Queues `event` for every subscriber, returning the wakers to wake once the queue is
+    /// unlocked
+    ///
+    /// Callers hold the write lock on the queue, so events land in the order the changes were
+    /// made even with writers on several threads
+    pub(crate) fn send(&self, event: QueueEvent) -> Vec<Waker> {
+        self.each_inbox(|inbox| {
+            if inbox.events.len() == INBOX_LEN {
+                inbox.events.clear();
+                inbox.events.push_back(QueueEvent::Changed);
+            } else {
+                inbox.events.push_back(event);
+            }
+        })
+    }
+
+    fn each_inbox(&self, mut f: impl FnMut(&mut Inbox)) -> Vec<Waker> {
+        let mut wakers = Vec::new();
+        lock(&self.inboxes).retain(|inbox| {
+            let Some(inbox) = inbox.upgrade() else {
+                return false;
+            };
+            let mut inbox = lock(&inbox);
+            f(&mut inbox);
+            wakers.extend(inbox.waker.take());
+            true
+        });
+        wakers
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        self.each_inbox(|inbox| inbox.closed = true).into_iter().for_each(Waker::wake);
+    }
+}
+
+/// Receives a [`QueueEvent`] for every change made through a [`SharedQueue`](crate::SharedQueue)
+/// after it subscribed, in order
+#[derive(Debug)]
+pub struct QueueSubscriber {
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+impl QueueSubscriber {
+    /// Waits for the next event, which is `None` once every handle to the queue is dropped
+    pub fn recv(&mut self) -> Recv<'_> {
+        Recv { subscriber: self }
+    }
+
+    /// The next event if there is one already, without waiting
+    pub fn try_recv(&mut self) -> Option<QueueEvent> {
+        lock(&self.inbox).events.pop_front()
+    }
+}
+
+/// The future returned by [`QueueSubscriber::recv`]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Recv<'a> {
+    subscriber: &'a mut QueueSubscriber,
+}
+
+impl Future for Recv<'_> {
+    type Output = Option<QueueEvent>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inbox = lock(&self.subscriber.inbox);
+        if let Some(event) = inbox.events.pop_front() {
+            Poll::Ready(Some(event))
+        } else if inbox.closed {
+            Poll::Ready(None)
+        } else {
+            inbox.waker = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+}
@@ -0,0 +1,48 @@
+#[cfg(feature = "std")]
+use std::collections::hash_map::RandomState;
+#[cfg(feature = "std")]
+use std::hash::{BuildHasher, Hasher};
+
+/// A small splitmix64 generator, good enough for shuffling a queue
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a float in `(0, 1]`
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an index in `0..n`, `n` must not be 0
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+impl Default for Rng {
+    #[cfg(feature = "std")]
+    fn default() -> Self {
+        // RandomState is seeded randomly per process, which saves depending on rand
+        Rng::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// Without std there is no entropy to draw from, so shuffles repeat until reseeded
+    #[cfg(not(feature = "std"))]
+    fn default() -> Self {
+        Rng::new(0x2545_F491_4F6C_DD1D)
+    }
+}
@@ -0,0 +1,873 @@
+use std::fmt::Debug;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "async")]
+use std::task::Waker;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "async")]
+use crate::notify::{Notifier, QueueSubscriber};
+use crate::{
+    EvictionPolicy, ExportEntry, HasDuration, ItemStats, LoopSetting, Queue, QueueCommand,
+    QueueDelta, QueueDiff, QueueError, QueueIntegrityError, QueueItem, QueueItemType,
+    QueuePosition, QueueSnapshot, QueueStats, ShuffleMode,
+};
+
+/// A [`Queue`] that can be shared between threads, cloning it only clones the handle
+///
+/// Every method of [`Queue`] is here too, except that the ones handing out references hand out
+/// copies instead, under a `_cloned` name where the plain one would be misleading
+#[derive(Debug)]
+pub struct SharedQueue<T, U> {
+    queue: Arc<RwLock<Queue<T, U>>>,
+    #[cfg(feature = "async")]
+    notifier: Arc<Notifier>,
+}
+
+/// What a call through a [`SharedQueue`] changed, as sent to every
+/// [`QueueSubscriber`](crate::QueueSubscriber) with the `async` feature
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum QueueEvent {
+    /// Items were added
+    Added,
+    /// Items were removed, upcoming or played
+    Removed,
+    /// The current item changed
+    Advanced,
+    /// Anything else, like a setting, the order of the items, or a restored snapshot
+    Changed,
+}
+
+impl<T, U> QueueCommand<T, U> {
+    fn event(&self) -> QueueEvent {
+        use QueueCommand::*;
+
+        match self {
+            Add { .. } | AddNext(_) | AddMulti { .. } | AddMultiNext(_) | AddAt { .. } => {
+                QueueEvent::Added
+            }
+            Insert { .. } => QueueEvent::Added,
+            Remove(_) | Clear | ClearUpcoming | ClearPlayed | ClearAll => QueueEvent::Removed,
+            Next | Skip | Prev | MoveTo(_) => QueueEvent::Advanced,
+            _ => QueueEvent::Changed,
+        }
+    }
+}
+
+// handles are sent to other threads, so they have to stay `Send + Sync + Clone`
+const _: () = {
+    fn _assert<T: Send + Sync + Clone>() {}
+    let _ = _assert::<SharedQueue<u32, Vec<u32>>>;
+};
+
+/// Another name for [`SharedQueue`]
+pub type SyncQueue<T, U> = SharedQueue<T, U>;
+
+impl<T, U> Clone for SharedQueue<T, U> {
+    fn clone(&self) -> Self {
+        SharedQueue {
+            queue: Arc::clone(&self.queue),
+            #[cfg(feature = "async")]
+            notifier: Arc::clone(&self.notifier),
+        }
+    }
+}
+
+impl<T, U> Default for SharedQueue<T, U> {
+    fn default() -> Self {
+        SharedQueue::from(Queue::default())
+    }
+}
+
+impl<T, U> From<Queue<T, U>> for SharedQueue<T, U> {
+    fn from(queue: Queue<T, U>) -> Self {
+        SharedQueue {
+            queue: Arc::new(RwLock::new(queue)),
+            #[cfg(feature = "async")]
+            notifier: Arc::default(),
+        }
+    }
+}
+
+impl<T, U> SharedQueue<T, U> {
+    // a panic while holding the lock can't leave the queue half written, so keep going
+    fn read(&self) -> RwLockReadGuard<'_, Queue<T, U>> {
+        self.queue.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Queue<T, U>> {
+        self.queue.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `f` with shared access to the queue
+    pub fn read_with<R>(&self, f: impl FnOnce(&Queue<T, U>) -> R) -> R {
+        f(&self.read())
+    }
+
+    /// Runs `f` with exclusive access to the queue
+    pub fn write_with<R>(&self, f: impl FnOnce(&mut Queue<T, U>) -> R) -> R {
+        self.change(QueueEvent::Changed, f)
+    }
+
+    // events are queued under the lock so they keep the order of the changes, but subscribers
+    // are only woken once it is let go, so they can read the queue as soon as they wake up
+    fn change<R>(&self, event: QueueEvent, f: impl FnOnce(&mut Queue<T, U>) -> R) -> R {
+        let mut queue = self.write();
+        let generation = queue.generation();
+        let result = f(&mut queue);
+
+        #[cfg(feature = "async")]
+        let wakers = if queue.generation() != generation {
+            self.notifier.send(event)
+        } else {
+            Vec::new()
+        };
+        #[cfg(not(feature = "async"))]
+        let _ = (generation, event);
+        drop(queue);
+
+        #[cfg(feature = "async")]
+        wakers.into_iter().for_each(Waker::wake);
+        result
+    }
+
+    /// Receives an event for every change made from now on, through any handle to the queue
+    #[cfg(feature = "async")]
+    pub fn subscribe(&self) -> QueueSubscriber {
+        self.notifier.subscribe()
+    }
+
+    /// Waits for the next change made through any handle to the queue
+    #[cfg(feature = "async")]
+    pub fn changed(&self) -> impl Future<Output = Option<QueueEvent>> {
+        let mut subscriber = self.subscribe();
+        async move { subscriber.recv().await }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.read().generation()
+    }
+
+    pub fn set_history_limit(&self, limit: Option<usize>) {
+        self.change(QueueEvent::Changed, |queue| queue.set_history_limit(limit))
+    }
+
+    pub fn history_limit(&self) -> Option<usize> {
+        self.read().history_limit()
+    }
+
+    pub fn history_dedup(&self) -> bool {
+        self.read().history_dedup()
+    }
+
+    pub fn set_max_len(&self, max_len: Option<usize>) {
+        self.change(QueueEvent::Changed, |queue| queue.set_max_len(max_len))
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        self.read().max_len()
+    }
+
+    pub fn set_eviction_policy(&self, policy: EvictionPolicy) {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.set_eviction_policy(policy)
+        })
+    }
+
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        self.read().eviction_policy()
+    }
+
+    pub fn reset_stats(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.reset_stats())
+    }
+
+    pub fn enable_loop(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.enable_loop())
+    }
+
+    pub fn disable_loop(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.disable_loop())
+    }
+
+    pub fn toggle_loop(&self) -> bool {
+        self.change(QueueEvent::Changed, |queue| queue.toggle_loop())
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.read().is_looping()
+    }
+
+    pub fn set_loop(&self, setting: LoopSetting) {
+        self.change(QueueEvent::Changed, |queue| queue.set_loop(setting))
+    }
+
+    pub fn loop_setting(&self) -> LoopSetting {
+        self.read().loop_setting()
+    }
+
+    pub fn loops_remaining(&self) -> Option<u32> {
+        self.read().loops_remaining()
+    }
+
+    pub fn set_repeat_one(&self, repeat: bool) {
+        self.change(QueueEvent::Changed, |queue| queue.set_repeat_one(repeat))
+    }
+
+    pub fn is_repeat_one(&self) -> bool {
+        self.read().is_repeat_one()
+    }
+
+    pub fn seed_shuffle(&self, seed: u64) {
+        self.change(QueueEvent::Changed, |queue| queue.seed_shuffle(seed))
+    }
+
+    pub fn disable_shuffle(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.disable_shuffle())
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.read().is_shuffled()
+    }
+
+    pub fn display_index_of_play_position(&self, n: usize) -> Option<usize> {
+        self.read().display_index_of_play_position(n)
+    }
+
+    pub fn play_position_of_index(&self, index: usize) -> Option<usize> {
+        self.read().play_position_of_index(index)
+    }
+
+    pub fn shuffle_mode(&self) -> ShuffleMode {
+        self.read().shuffle_mode()
+    }
+
+    pub fn trim_played_older_than(&self, age: Duration) -> usize {
+        self.change(QueueEvent::Removed, |queue| {
+            queue.trim_played_older_than(age)
+        })
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.read().is_at_end()
+    }
+
+    pub fn is_at_beginning(&self) -> bool {
+        self.read().is_at_beginning()
+    }
+
+    pub fn remaining_count(&self) -> usize {
+        self.read().remaining_count()
+    }
+
+    pub fn total_count(&self) -> usize {
+        self.read().total_count()
+    }
+
+    pub fn position_of_current(&self) -> usize {
+        self.read().position_of_current()
+    }
+
+    pub fn position_of_current_one_indexed(&self) -> usize {
+        self.read().position_of_current_one_indexed()
+    }
+
+    pub fn has_multi_items(&self) -> bool {
+        self.read().has_multi_items()
+    }
+
+    pub fn has_single_items(&self) -> bool {
+        self.read().has_single_items()
+    }
+
+    pub fn validate(&self) -> Result<(), QueueIntegrityError> {
+        self.read().validate()
+    }
+
+    pub fn export_with<F: FnMut(&QueueItem<T, U>) -> String>(
+        &self,
+        include_history: bool,
+        f: F,
+    ) -> String {
+        self.read().export_with(include_history, f)
+    }
+
+    /// A copy of [`Queue::stats`]
+    pub fn stats(&self) -> QueueStats {
+        *self.read().stats()
+    }
+
+    /// A copy of [`Queue::play_order`]
+    pub fn play_order(&self) -> Option<Vec<usize>> {
+        self.read().play_order().map(<[usize]>::to_vec)
+    }
+
+    /// Copies of the items [`Queue::peek_many`] returns
+    pub fn peek_many_cloned(&self, n: usize) -> Vec<QueueItem<T, U>>
+    where
+        T: Clone,
+        U: Clone,
+    {
+        self.read().peek_many(n).to_vec()
+    }
+
+    /// Copies of the items [`Queue::played_as_slice`] returns
+    pub fn played_cloned(&self) -> Vec<QueueItem<T, U>>
+    where
+        T: Clone,
+        U: Clone,
+    {
+        self.read().played_as_slice().to_vec()
+    }
+
+    /// Copies of the items [`Queue::history_with_times`] returns
+    pub fn history_with_times_cloned(&self) -> Vec<(QueueItem<T, U>, Option<SystemTime>)>
+    where
+        T: Clone,
+        U: Clone,
+    {
+        let queue = self.read();
+        queue.history_with_times().map(|(item, at)| (item.clone(), at)).collect()
+    }
+}
+
+impl<
+    T: Debug + Clone + PartialEq,
+    U: Debug + PartialEq + Clone + IntoIterator,
+> SharedQueue<T, U> {
+    pub fn enable_shuffle(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.enable_shuffle())
+    }
+
+    pub fn enable_weighted_shuffle<W: Into<f64>>(&self, weights: Vec<W>) {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.enable_weighted_shuffle(weights)
+        })
+    }
+
+    pub fn shuffle_weighted<F: Fn(&QueueItemType<T, U>) -> f64>(&self, weight: F) {
+        self.change(QueueEvent::Changed, |queue| queue.shuffle_weighted(weight))
+    }
+
+    pub fn shuffle_spread_by<K: Eq, F: Fn(&QueueItem<T, U>) -> K>(&self, key: F) {
+        self.change(QueueEvent::Changed, |queue| queue.shuffle_spread_by(key))
+    }
+
+    pub fn unshuffle(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.unshuffle())
+    }
+
+    pub fn set_items(&self, tracks: Vec<QueueItem<T, U>>) {
+        self.change(QueueEvent::Changed, |queue| queue.set_items(tracks))
+    }
+
+    pub fn add_item(&self, item: T, by_human: bool) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| queue.add_item(item, by_human))
+    }
+
+    pub fn add_item_with_priority(
+        &self,
+        item: T,
+        by_human: bool,
+        priority: i32,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_item_with_priority(item, by_human, priority)
+        })
+    }
+
+    pub fn add_item_next(&self, item: T) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| queue.add_item_next(item))
+    }
+
+    pub fn add_multi(
+        &self,
+        items: Vec<QueueItemType<T, U>>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| queue.add_multi(items, by_human))
+    }
+
+    pub fn extend_singles<I: IntoIterator<Item = T>>(
+        &self,
+        iter: I,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.extend_singles(iter, by_human)
+        })
+    }
+
+    pub fn extend_groups<I: IntoIterator<Item = U>>(
+        &self,
+        iter: I,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.extend_groups(iter, by_human)
+        })
+    }
+
+    pub fn add_at(
+        &self,
+        item: QueueItemType<T, U>,
+        position: QueuePosition,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_at(item, position, by_human)
+        })
+    }
+
+    pub fn add_multi_at(
+        &self,
+        items: Vec<QueueItemType<T, U>>,
+        position: QueuePosition,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_multi_at(items, position, by_human)
+        })
+    }
+
+    pub fn add_queue_items_at(
+        &self,
+        items: Vec<QueueItem<T, U>>,
+        position: QueuePosition,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_queue_items_at(items, position)
+        })
+    }
+
+    pub fn add_interleaved(
+        &self,
+        batches: Vec<Vec<QueueItemType<T, U>>>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_interleaved(batches, by_human)
+        })
+    }
+
+    pub fn batch_add<I: IntoIterator<Item = (QueueItemType<T, U>, bool)>>(
+        &self,
+        iter: I,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| queue.batch_add(iter))
+    }
+
+    pub fn add_multi_next(&self, items: Vec<QueueItemType<T, U>>) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| queue.add_multi_next(items))
+    }
+
+    pub fn remove_item(&self, remove_index: usize) -> Result<QueueItem<T, U>, QueueError> {
+        self.change(QueueEvent::Removed, |queue| queue.remove_item(remove_index))
+    }
+
+    pub fn dedup(&self) -> usize {
+        self.change(QueueEvent::Removed, |queue| queue.dedup())
+    }
+
+    pub fn dedup_by_key<K: PartialEq, F: FnMut(&QueueItemType<T, U>) -> K>(&self, f: F) -> usize {
+        self.change(QueueEvent::Removed, |queue| queue.dedup_by_key(f))
+    }
+
+    pub fn remove_duplicates(&self) -> usize {
+        self.change(QueueEvent::Removed, |queue| queue.remove_duplicates())
+    }
+
+    pub fn add_item_before(
+        &self,
+        index: usize,
+        item: QueueItemType<T, U>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_item_before(index, item, by_human)
+        })
+    }
+
+    pub fn add_item_after(
+        &self,
+        index: usize,
+        item: QueueItemType<T, U>,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.add_item_after(index, item, by_human)
+        })
+    }
+
+    pub fn insert(
+        &self,
+        index: usize,
+        new_item: QueueItemType<T, U>,
+        by_human: bool,
+        addhere: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.insert(index, new_item, by_human, addhere)
+        })
+    }
+
+    pub fn insert_range(
+        &self,
+        index: usize,
+        items: Vec<QueueItemType<T, U>>,
+        by_human: bool,
+        addhere: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| {
+            queue.insert_range(index, items, by_human, addhere)
+        })
+    }
+
+    pub fn clear(&self) {
+        self.change(QueueEvent::Removed, |queue| queue.clear())
+    }
+
+    pub fn clear_upcoming(&self) -> Vec<QueueItem<T, U>> {
+        self.change(QueueEvent::Removed, |queue| queue.clear_upcoming())
+    }
+
+    pub fn clear_except(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Removed, |queue| queue.clear_except(index))
+    }
+
+    pub fn take_played(&self) -> Vec<QueueItem<T, U>> {
+        self.change(QueueEvent::Removed, |queue| queue.take_played())
+    }
+
+    pub fn clear_played(&self) {
+        self.change(QueueEvent::Removed, |queue| queue.clear_played())
+    }
+
+    pub fn clear_all(&self) {
+        self.change(QueueEvent::Removed, |queue| queue.clear_all())
+    }
+
+    pub fn restart(&self) {
+        self.change(QueueEvent::Advanced, |queue| queue.restart())
+    }
+
+    pub fn extend_from_played(&self) -> Result<(), QueueError> {
+        self.change(QueueEvent::Added, |queue| queue.extend_from_played())
+    }
+
+    pub fn move_to(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Advanced, |queue| queue.move_to(index))
+    }
+
+    pub fn jump_to_by_value(&self, target: &QueueItemType<T, U>) -> Result<(), QueueError> {
+        self.change(QueueEvent::Advanced, |queue| queue.jump_to_by_value(target))
+    }
+
+    pub fn swap(&self, a: usize, b: usize) {
+        self.change(QueueEvent::Changed, |queue| queue.swap(a, b))
+    }
+
+    pub fn move_item(&self, from: usize, to: usize) {
+        self.change(QueueEvent::Changed, |queue| queue.move_item(from, to))
+    }
+
+    pub fn move_items(&self, indices: &[usize], to: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.move_items(indices, to))
+    }
+
+    pub fn promote(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.promote(index))
+    }
+
+    pub fn move_up(&self, index: usize, displace_current: bool) -> Result<usize, QueueError> {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.move_up(index, displace_current)
+        })
+    }
+
+    pub fn move_down(&self, index: usize, displace_current: bool) -> Result<usize, QueueError> {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.move_down(index, displace_current)
+        })
+    }
+
+    pub fn swap_adjacent(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.swap_adjacent(index))
+    }
+
+    pub fn move_item_up(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.move_item_up(index))
+    }
+
+    pub fn move_item_down(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.move_item_down(index))
+    }
+
+    pub fn swap_with_next(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.swap_with_next(index))
+    }
+
+    pub fn swap_with_prev(&self, index: usize) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.swap_with_prev(index))
+    }
+
+    pub fn sort_upcoming_by<K: Ord, F: FnMut(&QueueItemType<T, U>) -> K>(
+        &self,
+        keep_current: bool,
+        f: F,
+    ) {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.sort_upcoming_by(keep_current, f)
+        })
+    }
+
+    pub fn sort_upcoming_by_cached_key<K: Ord, F: FnMut(&QueueItemType<T, U>) -> K>(
+        &self,
+        keep_current: bool,
+        f: F,
+    ) {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.sort_upcoming_by_cached_key(keep_current, f)
+        })
+    }
+
+    pub fn reverse_upcoming(&self, keep_current: bool) {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.reverse_upcoming(keep_current)
+        })
+    }
+
+    pub fn sort_by_priority(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.sort_by_priority())
+    }
+
+    pub fn set_item_error(&self, index: usize, errored: bool) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.set_item_error(index, errored)
+        })
+    }
+
+    pub fn errored_indices(&self) -> Vec<usize> {
+        self.read().errored_indices()
+    }
+
+    pub fn current_track_in_group(&self) -> Option<T>
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.read().current_track_in_group()
+    }
+
+    pub fn total_item_count(&self) -> usize {
+        self.read().total_item_count()
+    }
+
+    pub fn item_stats(&self) -> ItemStats {
+        self.read().item_stats()
+    }
+
+    pub fn played_item_stats(&self) -> ItemStats {
+        self.read().played_item_stats()
+    }
+
+    pub fn set_by_human_for_range(
+        &self,
+        start: usize,
+        end: usize,
+        by_human: bool,
+    ) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| {
+            queue.set_by_human_for_range(start, end, by_human)
+        })
+    }
+
+    pub fn map_items<F: Fn(T) -> T>(&self, f: F) {
+        self.change(QueueEvent::Changed, |queue| queue.map_items(f))
+    }
+
+    pub fn filter_map_items<F>(&self, f: F)
+    where
+        F: Fn(QueueItemType<T, U>) -> Option<QueueItemType<T, U>>,
+    {
+        self.change(QueueEvent::Changed, |queue| queue.filter_map_items(f))
+    }
+
+    pub fn flatten(&self, index: usize) -> Result<usize, QueueError>
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.change(QueueEvent::Changed, |queue| queue.flatten(index))
+    }
+
+    pub fn flatten_all(&self) -> usize
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.change(QueueEvent::Changed, |queue| queue.flatten_all())
+    }
+
+    pub fn mapped_items<F: Fn(&T) -> T>(&self, f: F) -> Vec<QueueItem<T, U>> {
+        self.read().mapped_items(f)
+    }
+
+    pub fn position_of(&self, item: &QueueItemType<T, U>) -> Option<usize> {
+        self.read().position_of(item)
+    }
+
+    pub fn positions_of(&self, item: &QueueItemType<T, U>) -> Vec<usize> {
+        self.read().positions_of(item)
+    }
+
+    pub fn position_where<F: Fn(&QueueItem<T, U>) -> bool>(&self, pred: F) -> Option<usize> {
+        self.read().position_where(pred)
+    }
+
+    pub fn position_where_in_history<F>(&self, pred: F) -> Option<usize>
+    where
+        F: Fn(&QueueItem<T, U>) -> bool,
+    {
+        self.read().position_where_in_history(pred)
+    }
+
+    pub fn contains_single(&self, single: &T) -> bool
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.read().contains_single(single)
+    }
+
+    pub fn contains_single_in_history(&self, single: &T) -> bool
+    where
+        U: IntoIterator<Item = T>,
+    {
+        self.read().contains_single_in_history(single)
+    }
+
+    pub fn diff(&self, previous: &[QueueItem<T, U>]) -> Vec<QueueDelta> {
+        self.read().diff(previous)
+    }
+
+    pub fn diff_summary(&self, previous: &Queue<T, U>) -> QueueDiff<T, U> {
+        self.read().diff_summary(previous)
+    }
+
+    /// Advances the queue like [`Queue::next`], returning a copy of the new current item
+    pub fn next_cloned(&self) -> Result<QueueItem<T, U>, QueueError> {
+        self.change(QueueEvent::Advanced, |queue| queue.next().cloned())
+    }
+
+    /// Skips like [`Queue::skip`], returning a copy of the new current item
+    pub fn skip_cloned(&self) -> Result<QueueItem<T, U>, QueueError> {
+        self.change(QueueEvent::Advanced, |queue| queue.skip().cloned())
+    }
+
+    /// Goes back like [`Queue::prev`], returning a copy of the new current item
+    pub fn prev_cloned(&self) -> Result<QueueItem<T, U>, QueueError> {
+        self.change(QueueEvent::Advanced, |queue| queue.prev().cloned())
+    }
+
+    /// Goes back like [`Queue::prev_n`], returning a copy of the new current item
+    pub fn prev_n_cloned(&self, n: usize) -> Result<QueueItem<T, U>, QueueError> {
+        self.change(QueueEvent::Advanced, |queue| queue.prev_n(n).cloned())
+    }
+
+    /// Returns a copy of the current item
+    pub fn current_cloned(&self) -> Result<QueueItem<T, U>, QueueError> {
+        self.read().current().cloned()
+    }
+
+    /// Removes the current item like [`Queue::remove_current`], returning a copy of the new
+    /// current item
+    #[allow(clippy::type_complexity)]
+    pub fn remove_current_cloned(
+        &self,
+        to_played: bool,
+    ) -> Result<(QueueItem<T, U>, Option<QueueItem<T, U>>), QueueError> {
+        self.change(QueueEvent::Removed, |queue| {
+            queue.remove_current(to_played).map(|(removed, next)| (removed, next.cloned()))
+        })
+    }
+
+    /// Copies of the items [`Queue::next_n`] returns
+    pub fn next_n_cloned(&self, n: usize) -> Vec<(QueueItem<T, U>, usize)> {
+        let queue = self.read();
+        queue.next_n(n).into_iter().map(|(item, index)| (item.clone(), index)).collect()
+    }
+
+    /// A copy of the item [`Queue::find_where`] finds
+    pub fn find_where_cloned<F: Fn(&QueueItem<T, U>) -> bool>(
+        &self,
+        pred: F,
+    ) -> Option<QueueItem<T, U>> {
+        self.read().find_where(pred).cloned()
+    }
+
+    /// A copy of the item [`Queue::find_where_in_history`] finds
+    pub fn find_where_in_history_cloned<F>(&self, pred: F) -> Option<QueueItem<T, U>>
+    where
+        F: Fn(&QueueItem<T, U>) -> bool,
+    {
+        self.read().find_where_in_history(pred).cloned()
+    }
+
+    pub fn clone_remaining(&self) -> Vec<QueueItem<T, U>> {
+        self.read().clone_remaining()
+    }
+
+    pub fn checkpoint(&self) -> QueueSnapshot<T, U> {
+        self.read().checkpoint()
+    }
+
+    pub fn restore(&self, snapshot: QueueSnapshot<T, U>) {
+        self.change(QueueEvent::Changed, |queue| queue.restore(snapshot))
+    }
+
+    pub fn check_played(&self, limit: usize) {
+        self.change(QueueEvent::Removed, |queue| queue.check_played(limit))
+    }
+
+    pub fn set_history_dedup(&self, dedup: bool) {
+        self.change(QueueEvent::Changed, |queue| queue.set_history_dedup(dedup))
+    }
+
+    pub fn begin_transaction(&self) {
+        self.change(QueueEvent::Changed, |queue| queue.begin_transaction())
+    }
+
+    pub fn commit(&self) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.commit())
+    }
+
+    pub fn rollback(&self) -> Result<(), QueueError> {
+        self.change(QueueEvent::Changed, |queue| queue.rollback())
+    }
+
+    pub fn transaction_depth(&self) -> usize {
+        self.read().transaction_depth()
+    }
+
+    pub fn apply(&self, command: QueueCommand<T, U>) -> Result<(), QueueError> {
+        self.change(command.event(), |queue| queue.apply(command))
+    }
+}
+
+impl<T: HasDuration, U: HasDuration> SharedQueue<T, U> {
+    pub fn remaining_duration(&self) -> Duration {
+        self.read().remaining_duration()
+    }
+
+    pub fn played_duration(&self) -> Duration {
+        self.read().played_duration()
+    }
+}
+
+impl<T: ExportEntry, U: Clone + IntoIterator> SharedQueue<T, U>
+where
+    U::Item: ExportEntry,
+{
+    pub fn export_m3u(&self, include_history: bool) -> String {
+        self.read().export_m3u(include_history)
+    }
+}
@@ -0,0 +1,1508 @@
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::time::Duration;
+
+use crate::*;
+
+type TestQueue = Queue<u32, Vec<u32>>;
+
+/// A queue of Singles `0..len`, added the usual way
+fn queue(len: u32) -> TestQueue {
+    TestQueue::from((0..len).collect::<Vec<_>>())
+}
+
+/// A copy of everything but the generation and open transactions
+fn copy(queue: &TestQueue) -> TestQueue {
+    let mut copy = TestQueue::default();
+    copy.restore(queue.checkpoint());
+    copy
+}
+
+fn values(items: &[QueueItem<u32, Vec<u32>>]) -> Vec<u32> {
+    items
+        .iter()
+        .map(|item| match item.item {
+            QueueItemType::Single(value) => value,
+            QueueItemType::Multi(_) => panic!("not a Single"),
+        })
+        .collect()
+}
+
+fn states(queue: &TestQueue) -> Vec<QueueState> {
+    queue.items.iter().map(|item| item.state).collect()
+}
+
+#[test]
+fn move_item_onto_itself() {
+    for index in 0..4 {
+        let mut queue = queue(4);
+        queue.enable_shuffle();
+        let items = queue.items.clone();
+        let shuffle = queue.shuffle.clone();
+
+        queue.move_item(index, index);
+        assert_eq!(queue.items, items);
+        assert_eq!(queue.shuffle, shuffle);
+        assert!(queue.validate().is_ok());
+    }
+
+    let mut queue = queue(3);
+    assert_eq!(queue.apply(QueueCommand::Move { from: 2, to: 2 }).ok(), Some(()));
+    assert_eq!(values(&queue.items), vec![0, 1, 2]);
+    assert_eq!(states(&queue)[2], QueueState::AddHere);
+}
+
+#[test]
+fn remove_duplicates_against_history_and_itself() {
+    let mut queue = queue(4);
+    queue.extend_singles([1, 5, 5, 2, 6], false).unwrap();
+    queue.next().unwrap();
+    queue.next().unwrap();
+    assert_eq!(values(&queue.items), vec![2, 3, 1, 5, 5, 2, 6]);
+
+    let generation = queue.generation();
+    assert_eq!(queue.remove_duplicates(), 3);
+    assert_eq!(queue.generation(), generation + 1);
+    assert_eq!(values(&queue.items), vec![2, 3, 5, 6]);
+    assert_eq!(states(&queue)[3], QueueState::AddHere);
+}
+
+#[test]
+fn prev_n_matches_repeated_prev() {
+    let mut played = queue(6);
+    played.extend_groups([vec![10, 11, 12]], false).unwrap();
+    played.add_item_next(20).unwrap();
+    for _ in 0..7 {
+        played.next().unwrap();
+    }
+    played.add_item(30, true).unwrap();
+
+    for n in 0..=8 {
+        let mut stepped = copy(&played);
+        let mut rewound = copy(&played);
+        let stepped_ok = (0..n).try_for_each(|_| stepped.prev().map(|_| ())).is_ok();
+
+        let generation = rewound.generation();
+        let rewound_ok = rewound.prev_n(n).is_ok();
+        assert_eq!(rewound.generation(), generation + 1);
+        assert_eq!(rewound_ok, stepped_ok, "n = {n}");
+        if rewound_ok {
+            assert_eq!(rewound.items, stepped.items, "n = {n}");
+            assert_eq!(rewound.played, stepped.played, "n = {n}");
+        } else {
+            // not enough history leaves the queue alone
+            assert_eq!(rewound.items, played.items);
+        }
+
+        rewound.add_item(40, false).unwrap();
+        if rewound_ok {
+            stepped.add_item(40, false).unwrap();
+            assert_eq!(rewound.items, stepped.items, "n = {n}");
+        }
+    }
+}
+
+#[test]
+fn add_before_and_after_keep_by_human() {
+    let mut queue = queue(3);
+    queue.add_item_before(2, QueueItemType::Single(10), true).unwrap();
+    queue.add_item_after(0, QueueItemType::Single(11), false).unwrap();
+    assert_eq!(values(&queue.items), vec![0, 11, 1, 10, 2]);
+    assert!(queue.items[3].by_human && !queue.items[1].by_human);
+    // the item inserted before the AddHere item takes the marker over
+    assert_eq!(queue.items[3].state, QueueState::AddHere);
+    assert_eq!(queue.items[4].state, QueueState::NoState);
+
+    let mut empty = TestQueue::default();
+    empty.add_item_after(0, QueueItemType::Single(1), true).unwrap();
+    assert_eq!(states(&empty), vec![QueueState::AddHere]);
+}
+
+#[test]
+fn flatten_keeps_the_current_track() {
+    let mut queue = TestQueue::default();
+    queue.extend_groups([vec![10, 11, 12, 13]], false).unwrap();
+    queue.add_item(1, false).unwrap();
+    queue.next().unwrap();
+    queue.next().unwrap();
+    assert_eq!(queue.current_track_in_group(), Some(12));
+
+    assert_eq!(queue.flatten(0).ok(), Some(2));
+    assert_eq!(values(&queue.items), vec![12, 13, 1]);
+    assert_eq!(values(&queue.played), vec![10, 11]);
+    assert_eq!(states(&queue)[2], QueueState::AddHere);
+    assert_eq!(queue.next().unwrap().item, QueueItemType::Single(13));
+}
+
+#[test]
+fn flatten_hands_the_group_on_to_its_tracks() {
+    let group = QueueItem::from_item_type(QueueItemType::Multi(vec![10, 11, 12]), true)
+        .with_priority(3)
+        .with_meta("album");
+    let mut queue = TestQueue::default();
+    queue.add_queue_items_at(vec![group], QueuePosition::End).unwrap();
+    queue.set_item_error(0, true).unwrap();
+    queue.items[0].track = 1;
+
+    assert_eq!(queue.flatten(0).ok(), Some(2));
+    assert_eq!(values(&queue.played), vec![10]);
+    assert_eq!(queue.played[0].state, QueueState::Played);
+    assert_eq!(values(&queue.items), vec![11, 12]);
+    for track in queue.played.iter().chain(&queue.items) {
+        assert!(track.by_human && track.errored);
+        assert_eq!(track.priority, 3);
+        assert_eq!(track.meta::<&str>(), Some(&"album"));
+    }
+    assert!(queue.validate().is_ok());
+}
+
+#[test]
+fn filter_map_keeps_the_current_track() {
+    let mut queue = TestQueue::default();
+    queue.extend_groups([vec![10, 11, 12], vec![20]], false).unwrap();
+    queue.next().unwrap();
+
+    queue.filter_map_items(|item| match item {
+        QueueItemType::Multi(tracks) => {
+            Some(QueueItemType::Multi(tracks.into_iter().map(|t| t + 100).collect()))
+        }
+        single => Some(single),
+    });
+    assert_eq!(queue.items[0].track, 1);
+    assert_eq!(queue.current_track_in_group(), Some(111));
+}
+
+#[test]
+fn eviction_keeps_the_item_added_next_to() {
+    let mut queue = queue(4);
+    queue.set_max_len(Some(4));
+    queue.set_eviction_policy(EvictionPolicy::DropOldestAuto);
+
+    // the last item is the furthest out, but it is the one being added before
+    queue.add_item_before(3, QueueItemType::Single(10), true).unwrap();
+    assert_eq!(values(&queue.items), vec![0, 1, 10, 3]);
+    assert_eq!(states(&queue)[2], QueueState::AddHere);
+    assert_eq!(states(&queue)[3], QueueState::NoState);
+
+    queue.add_item_after(3, QueueItemType::Single(11), true).unwrap();
+    assert_eq!(values(&queue.items), vec![0, 10, 3, 11]);
+    assert!(queue.validate().is_ok());
+}
+
+#[test]
+fn extend_from_played_respects_max_len() {
+    let mut queue = queue(4);
+    queue.next().unwrap();
+    queue.next().unwrap();
+    queue.set_max_len(Some(3));
+
+    assert!(matches!(queue.extend_from_played(), Err(QueueError::Full)));
+    assert_eq!(values(&queue.items), vec![2, 3]);
+    assert_eq!(queue.played.len(), 2);
+
+    queue.set_max_len(Some(4));
+    queue.extend_from_played().unwrap();
+    assert_eq!(values(&queue.items), vec![2, 3, 0, 1]);
+    assert!(queue.played.is_empty());
+}
+
+#[test]
+fn adds_that_dont_fit_say_so() {
+    let mut queue = queue(3);
+    queue.set_max_len(Some(3));
+    let before = values(&queue.items);
+
+    let single = || vec![QueueItemType::Single(10)];
+    let results = [
+        queue.add_item(10, true),
+        queue.add_item_with_priority(10, true, 1),
+        queue.add_item_next(10),
+        queue.add_multi(single(), true),
+        queue.add_multi_next(single()),
+        queue.extend_singles([10], true),
+        queue.extend_groups([vec![10]], true),
+        queue.batch_add([(QueueItemType::Single(10), true)]),
+        queue.add_interleaved(vec![single(), single()], true),
+    ];
+    for result in results {
+        assert!(matches!(result, Err(QueueError::Full)));
+    }
+    assert_eq!(values(&queue.items), before);
+    assert!(queue.validate().is_ok());
+}
+
+#[test]
+fn loop_times_loops_that_many_times_then_stops() {
+    let mut queue = queue(3);
+    queue.set_loop(LoopSetting::Times(2));
+    for _ in 0..2 {
+        let mut advanced = 0;
+        while queue.next().is_ok() {
+            advanced += 1;
+        }
+        // once through and twice more, less the item that started out current
+        assert_eq!(advanced, 3 * 3 - 1);
+        assert_eq!(queue.loops_remaining(), Some(0));
+        assert!(!queue.is_looping());
+        assert!(matches!(queue.next(), Err(QueueError::EmptyQueue)));
+
+        queue.restart();
+        assert_eq!(queue.loops_remaining(), Some(2));
+        assert_eq!(values(&queue.items), vec![0, 1, 2]);
+    }
+    assert_eq!(queue.stats().times_looped, 4);
+}
+
+#[test]
+fn seeded_shuffles_repeat() {
+    let shuffled = |seed, weighted: bool| {
+        let mut queue = queue(20);
+        queue.seed_shuffle(seed);
+        if weighted {
+            queue.enable_weighted_shuffle((1..=20).map(f64::from).collect());
+        } else {
+            queue.enable_shuffle();
+        }
+        values(&queue.items)
+    };
+    for weighted in [false, true] {
+        assert_eq!(shuffled(1, weighted), shuffled(1, weighted));
+        assert_ne!(shuffled(1, weighted), shuffled(2, weighted));
+    }
+}
+
+#[test]
+fn weighted_shuffles_take_any_weight() {
+    for seed in 0..20 {
+        let mut queue = queue(5);
+        queue.seed_shuffle(seed);
+        queue.enable_weighted_shuffle(vec![1.0, f64::INFINITY, 1.0, f64::NAN, f64::INFINITY]);
+        assert!(queue.validate().is_ok());
+
+        // the infinite weights go first and the NaN one last
+        let order = values(&queue.items);
+        assert_eq!(order[0], 0);
+        assert!(order[1..3].contains(&1) && order[1..3].contains(&4), "{order:?}");
+        assert_eq!(order[3..], [2, 3]);
+
+        queue.shuffle_weighted(|_| f64::NEG_INFINITY);
+        assert!(queue.validate().is_ok());
+        assert_eq!(queue.items.len(), 5);
+    }
+}
+
+/// Checks `next_n` against calling `next` the same number of times
+fn assert_next_n_lockstep(mut queue: TestQueue, n: usize) {
+    let predicted: Vec<_> = queue
+        .next_n(n)
+        .into_iter()
+        .map(|(item, track)| (item.item.clone(), track))
+        .collect();
+    let mut actual = Vec::new();
+    while actual.len() < n {
+        match queue.next() {
+            Ok(item) => actual.push((item.item.clone(), item.track)),
+            Err(_) => break,
+        }
+    }
+    assert_eq!(predicted, actual);
+}
+
+#[test]
+fn next_n_matches_next() {
+    let build = |setup: &dyn Fn(&mut TestQueue)| {
+        let mut queue = queue(4);
+        queue.extend_groups([vec![10, 11, 12]], false).unwrap();
+        queue.add_item(5, true).unwrap();
+        queue.seed_shuffle(7);
+        setup(&mut queue);
+        queue
+    };
+    let setups: [&dyn Fn(&mut TestQueue); 7] = [
+        &|_| (),
+        &|queue| queue.enable_loop(),
+        &|queue| queue.set_loop(LoopSetting::Times(1)),
+        &|queue| {
+            queue.enable_loop();
+            queue.enable_shuffle();
+        },
+        &|queue| queue.set_repeat_one(true),
+        &|queue| {
+            queue.set_item_error(2, true).unwrap();
+            queue.set_item_error(0, true).unwrap();
+            queue.enable_loop();
+        },
+        &|queue| {
+            queue.enable_loop();
+            queue.set_history_limit(Some(3));
+            queue.next().unwrap();
+            queue.next().unwrap();
+            queue.prev().unwrap();
+        },
+    ];
+    for setup in setups {
+        for n in [0, 1, 3, 8, 20] {
+            assert_next_n_lockstep(build(setup), n);
+        }
+    }
+}
+
+/// Plays a looping queue to its end and returns the sorted values of the next cycle
+fn next_cycle(queue: &mut TestQueue) -> Vec<u32> {
+    let generation = queue.stats().times_looped;
+    while queue.stats().times_looped == generation {
+        queue.next().unwrap();
+    }
+    let mut cycle = values(&queue.items);
+    cycle.sort_unstable();
+    cycle
+}
+
+#[test]
+fn reshuffled_cycle_has_each_item_once() {
+    // each returns the items that should come back in the next cycle
+    type Setup = dyn Fn(&mut TestQueue) -> Vec<u32>;
+    let setups: [&Setup; 4] = [
+        &|_| vec![0, 1, 2],
+        &|queue| {
+            queue.set_repeat_one(true);
+            for _ in 0..3 {
+                queue.next().unwrap();
+            }
+            queue.set_repeat_one(false);
+            vec![0, 1, 2]
+        },
+        &|queue| {
+            queue.next().unwrap();
+            queue.next().unwrap();
+            queue.prev().unwrap();
+            queue.prev().unwrap();
+            vec![0, 1, 2]
+        },
+        &|queue| {
+            queue.next().unwrap();
+            queue.add_item(3, false).unwrap();
+            let (removed, _) = queue.remove_current(true).unwrap();
+            queue.unshuffle();
+            queue.enable_shuffle();
+            (0..4).filter(|&i| removed.item != QueueItemType::Single(i)).collect()
+        },
+    ];
+    for (i, setup) in setups.iter().enumerate() {
+        for shuffled in [false, true] {
+            let mut queue = queue(3);
+            queue.seed_shuffle(i as u64);
+            queue.enable_loop();
+            if shuffled {
+                queue.enable_shuffle();
+            }
+            let expected = setup(&mut queue);
+            assert_eq!(next_cycle(&mut queue), expected, "setup {i}, shuffled {shuffled}");
+            assert_eq!(next_cycle(&mut queue), expected, "setup {i}, shuffled {shuffled}");
+        }
+    }
+}
+
+#[test]
+fn restore_puts_back_every_setting() {
+    let mut queue = queue(5);
+    queue.seed_shuffle(3);
+    queue.enable_loop();
+    queue.next().unwrap();
+    queue.next().unwrap();
+    let snapshot = queue.checkpoint();
+    let mut untouched = copy(&queue);
+
+    queue.set_history_limit(Some(1));
+    queue.set_history_dedup(true);
+    queue.set_max_len(Some(2));
+    queue.set_eviction_policy(EvictionPolicy::DropOldestAuto);
+    queue.reset_stats();
+    queue.enable_shuffle();
+    queue.next().unwrap();
+    queue.restore(snapshot.clone());
+    assert_eq!(queue.checkpoint(), snapshot);
+
+    // the queue goes on the same as one that was never changed, shuffles and all
+    for queue in [&mut queue, &mut untouched] {
+        queue.prev().unwrap();
+        queue.prev().unwrap();
+        queue.add_item(9, false).unwrap();
+        queue.enable_shuffle();
+    }
+    assert_eq!(queue.checkpoint(), untouched.checkpoint());
+}
+
+/// A queue with some of everything, history, a Multi item, an errored item and a shuffle
+fn busy_queue() -> TestQueue {
+    let mut queue = queue(6);
+    queue.seed_shuffle(11);
+    queue.extend_groups([vec![10, 11, 12]], false).unwrap();
+    queue.add_item_with_priority(20, true, 2).unwrap();
+    queue.next().unwrap();
+    queue.next().unwrap();
+    queue.set_repeat_one(true);
+    queue.next().unwrap();
+    queue.set_repeat_one(false);
+    queue.enable_shuffle();
+    queue.set_item_error(3, true).unwrap();
+    queue.add_item(1, false).unwrap();
+    queue.add_item(4, false).unwrap();
+    queue.set_loop(LoopSetting::Times(3));
+    queue
+}
+
+type Edit = (&'static str, &'static dyn Fn(&mut TestQueue));
+
+const EDITS: &[Edit] = &[
+    ("set_history_limit", &|q| q.set_history_limit(Some(1))),
+    ("set_max_len", &|q| q.set_max_len(Some(3))),
+    ("set_eviction_policy", &|q| q.set_eviction_policy(EvictionPolicy::DropOldestAuto)),
+    ("reset_stats", &|q| q.reset_stats()),
+    ("enable_loop", &|q| q.enable_loop()),
+    ("disable_loop", &|q| q.disable_loop()),
+    ("toggle_loop", &|q| {
+        q.toggle_loop();
+    }),
+    ("set_loop", &|q| q.set_loop(LoopSetting::Times(2))),
+    ("set_repeat_one", &|q| q.set_repeat_one(true)),
+    ("seed_shuffle", &|q| q.seed_shuffle(5)),
+    ("disable_shuffle", &|q| q.disable_shuffle()),
+    #[cfg(feature = "std")]
+    ("trim_played_older_than", &|q| {
+        q.trim_played_older_than(Duration::ZERO);
+    }),
+    ("enable_shuffle", &|q| q.enable_shuffle()),
+    ("shuffle_weighted", &|q| {
+        q.shuffle_weighted(|item| if matches!(item, QueueItemType::Multi(_)) { 9.0 } else { 1.0 });
+    }),
+    ("enable_weighted_shuffle", &|q| q.enable_weighted_shuffle(vec![1.0, 5.0, 0.5])),
+    ("shuffle_spread_by", &|q| q.shuffle_spread_by(|item| item.by_human)),
+    ("unshuffle", &|q| q.unshuffle()),
+    ("set_items", &|q| {
+        q.set_items(vec![QueueItem::from_item_type(QueueItemType::Single(1), true)]);
+    }),
+    ("add_item", &|q| {
+        let _ = q.add_item(30, true);
+    }),
+    ("add_item_with_priority", &|q| {
+        let _ = q.add_item_with_priority(30, true, 1);
+    }),
+    ("add_item_next", &|q| {
+        let _ = q.add_item_next(30);
+    }),
+    ("add_multi", &|q| {
+        let _ = q.add_multi(vec![QueueItemType::Multi(vec![30, 31])], false);
+    }),
+    ("add_multi_next", &|q| {
+        let _ = q.add_multi_next(vec![QueueItemType::Single(30)]);
+    }),
+    ("extend_singles", &|q| {
+        let _ = q.extend_singles([30, 31], false);
+    }),
+    ("extend_groups", &|q| {
+        let _ = q.extend_groups([vec![30, 31]], false);
+    }),
+    ("add_at", &|q| {
+        let _ = q.add_at(QueueItemType::Single(30), QueuePosition::End, true);
+    }),
+    ("add_interleaved", &|q| {
+        let sources = vec![vec![QueueItemType::Single(30)], vec![QueueItemType::Single(31)]];
+        let _ = q.add_interleaved(sources, true);
+    }),
+    ("add_multi_at", &|q| {
+        let _ = q.add_multi_at(vec![QueueItemType::Single(30)], QueuePosition::Index(2), true);
+    }),
+    ("add_queue_items_at", &|q| {
+        let item = QueueItem::from_item_type(QueueItemType::Single(30), true);
+        let _ = q.add_queue_items_at(vec![item], QueuePosition::AfterCurrent);
+    }),
+    ("batch_add", &|q| {
+        let _ = q.batch_add([(QueueItemType::Single(30), true)]);
+    }),
+    ("remove_item", &|q| {
+        let _ = q.remove_item(2);
+    }),
+    ("remove_current", &|q| {
+        let _ = q.remove_current(true);
+    }),
+    ("dedup", &|q| {
+        q.dedup();
+    }),
+    ("dedup_by_key", &|q| {
+        q.dedup_by_key(|item| matches!(item, QueueItemType::Multi(_)));
+    }),
+    ("remove_duplicates", &|q| {
+        q.remove_duplicates();
+    }),
+    ("add_item_before", &|q| {
+        let _ = q.add_item_before(1, QueueItemType::Single(30), true);
+    }),
+    ("add_item_after", &|q| {
+        let _ = q.add_item_after(1, QueueItemType::Single(30), true);
+    }),
+    ("insert", &|q| {
+        let _ = q.insert(2, QueueItemType::Single(30), true, true);
+    }),
+    ("insert_range", &|q| {
+        let _ = q.insert_range(1, vec![QueueItemType::Single(30)], true, true);
+    }),
+    ("clear", &|q| q.clear()),
+    ("clear_upcoming", &|q| {
+        q.clear_upcoming();
+    }),
+    ("clear_except", &|q| {
+        let _ = q.clear_except(2);
+    }),
+    ("drain_played", &|q| {
+        q.drain_played().count();
+    }),
+    ("take_played", &|q| {
+        q.take_played();
+    }),
+    ("clear_played", &|q| q.clear_played()),
+    ("clear_all", &|q| q.clear_all()),
+    ("restart", &|q| q.restart()),
+    ("extend_from_played", &|q| {
+        let _ = q.extend_from_played();
+    }),
+    ("move_to", &|q| {
+        let _ = q.move_to(4);
+    }),
+    ("jump_to_by_value", &|q| {
+        let _ = q.jump_to_by_value(&QueueItemType::Single(5));
+    }),
+    ("swap", &|q| {
+        if q.items.len() > 3 {
+            q.swap(1, 3);
+        }
+    }),
+    ("move_item", &|q| {
+        if q.items.len() > 4 {
+            q.move_item(4, 1);
+        }
+    }),
+    ("move_items", &|q| {
+        let _ = q.move_items(&[1, 3], 5);
+    }),
+    ("promote", &|q| {
+        let _ = q.promote(4);
+    }),
+    ("move_up", &|q| {
+        let _ = q.move_up(1, true);
+    }),
+    ("move_down", &|q| {
+        let _ = q.move_down(0, true);
+    }),
+    ("move_item_up", &|q| {
+        let _ = q.move_item_up(2);
+    }),
+    ("move_item_down", &|q| {
+        let _ = q.move_item_down(1);
+    }),
+    ("swap_with_next", &|q| {
+        let _ = q.swap_with_next(1);
+    }),
+    ("swap_with_prev", &|q| {
+        let _ = q.swap_with_prev(2);
+    }),
+    ("swap_adjacent", &|q| {
+        let _ = q.swap_adjacent(2);
+    }),
+    ("sort_upcoming_by", &|q| {
+        q.sort_upcoming_by(false, |item| matches!(item, QueueItemType::Multi(_)));
+    }),
+    ("sort_upcoming_by_cached_key", &|q| {
+        q.sort_upcoming_by_cached_key(false, |item| matches!(item, QueueItemType::Multi(_)));
+    }),
+    ("reverse_upcoming", &|q| q.reverse_upcoming(false)),
+    ("sort_by_priority", &|q| q.sort_by_priority()),
+    ("next", &|q| {
+        let _ = q.next();
+    }),
+    ("skip", &|q| {
+        let _ = q.skip();
+    }),
+    ("set_item_error", &|q| {
+        let _ = q.set_item_error(0, true);
+    }),
+    ("prev", &|q| {
+        let _ = q.prev();
+    }),
+    ("prev_n", &|q| {
+        let _ = q.prev_n(2);
+    }),
+    ("set_by_human_for_range", &|q| {
+        let _ = q.set_by_human_for_range(0, 4, true);
+    }),
+    ("map_items", &|q| q.map_items(|value| value + 100)),
+    ("filter_map_items", &|q| q.filter_map_items(|item| Some(item).filter(|_| false))),
+    ("flatten", &|q| {
+        let group = q.position_where(|item| matches!(item.item, QueueItemType::Multi(_)));
+        let _ = q.flatten(group.unwrap_or(0));
+    }),
+    ("flatten_all", &|q| {
+        q.flatten_all();
+    }),
+    ("restore", &|q| q.restore(TestQueue::from(vec![1, 2]).checkpoint())),
+    ("check_played", &|q| q.check_played(1)),
+    ("set_history_dedup", &|q| q.set_history_dedup(true)),
+    ("apply", &|q| {
+        let _ = q.apply(QueueCommand::Move { from: 1, to: 4 });
+    }),
+    ("play through", &|q| {
+        for _ in 0..30 {
+            if q.next().is_err() {
+                break;
+            }
+        }
+    }),
+];
+
+#[test]
+fn rollback_undoes_every_method() {
+    for (name, edit) in EDITS {
+        let mut queue = busy_queue();
+        let before = queue.checkpoint();
+        let generation = queue.generation();
+
+        queue.begin_transaction();
+        edit(&mut queue);
+        assert_ne!(queue.checkpoint(), before, "{name} changed nothing");
+        queue.rollback().unwrap();
+        assert_eq!(queue.checkpoint(), before, "{name}");
+        assert!(queue.generation() > generation, "{name}");
+        assert_eq!(queue.transaction_depth(), 0);
+    }
+}
+
+#[test]
+fn nested_rollbacks_undo_their_own_edits() {
+    for (outer, first) in EDITS.iter().step_by(3) {
+        for (inner, second) in EDITS.iter().skip(1).step_by(4) {
+            let mut queue = busy_queue();
+            let before = queue.checkpoint();
+
+            queue.begin_transaction();
+            first(&mut queue);
+            let between = queue.checkpoint();
+            queue.begin_transaction();
+            second(&mut queue);
+            queue.rollback().unwrap();
+            assert_eq!(queue.checkpoint(), between, "{outer} then {inner}");
+
+            // a committed inner transaction is still undone by the outer one
+            queue.begin_transaction();
+            second(&mut queue);
+            queue.commit().unwrap();
+            queue.rollback().unwrap();
+            assert_eq!(queue.checkpoint(), before, "{outer} then {inner}");
+        }
+    }
+    assert!(TestQueue::default().rollback().is_err());
+    assert!(TestQueue::default().commit().is_err());
+}
+
+#[test]
+fn transactions_copy_only_what_they_drop() {
+    use alloc::rc::Rc;
+
+    let values: Vec<_> = (0..5).map(Rc::new).collect();
+    let mut queue: Queue<Rc<u32>, Vec<Rc<u32>>> = Queue::from(values);
+    let values: Vec<_> = queue.items.iter().map(|item| item.item.clone()).collect();
+    let copies = |values: &[QueueItemType<Rc<u32>, Vec<Rc<u32>>>]| -> Vec<usize> {
+        values
+            .iter()
+            .map(|value| match value {
+                QueueItemType::Single(value) => Rc::strong_count(value) - 2,
+                QueueItemType::Multi(_) => unreachable!(),
+            })
+            .collect()
+    };
+
+    queue.begin_transaction();
+    queue.next().unwrap();
+    queue.swap(1, 2);
+    assert_eq!(copies(&values), vec![0; 5]);
+    // the removed item is handed out, so the transaction keeps a copy of its own
+    let removed = queue.remove_item(3).unwrap();
+    assert_eq!(copies(&values), vec![0, 0, 0, 0, 1]);
+    drop(removed);
+    queue.rollback().unwrap();
+    assert_eq!(copies(&values), vec![0; 5]);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn shared_queues_take_writes_from_many_threads() {
+    extern crate std;
+    use std::thread;
+
+    let queue: SharedQueue<u32, Vec<u32>> = SharedQueue::default();
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                for value in 0..25 {
+                    queue.add_item(writer * 100 + value, true).unwrap();
+                    let _ = queue.next_cloned();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(queue.total_count(), 100);
+    assert_eq!(queue.generation(), 200);
+    assert!(queue.validate().is_ok());
+}
+
+#[cfg(feature = "async")]
+mod notify {
+    use std::future::Future;
+    use std::sync::{Arc, Barrier};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    use crate::*;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn subscribers_get_each_change_in_order() {
+        let queue: SharedQueue<u32, Vec<u32>> = SharedQueue::default();
+        let mut subscriber = queue.subscribe();
+        queue.add_item(0, true).unwrap();
+        queue.add_item(1, true).unwrap();
+        queue.add_item(2, true).unwrap();
+        queue.next_cloned().unwrap();
+        queue.remove_item(1).unwrap();
+        queue.apply(QueueCommand::SetRepeatOne(true)).unwrap();
+        queue.is_at_end();
+        queue.current_cloned().unwrap();
+
+        let events = [
+            QueueEvent::Added,
+            QueueEvent::Added,
+            QueueEvent::Added,
+            QueueEvent::Advanced,
+            QueueEvent::Removed,
+            QueueEvent::Changed,
+        ];
+        for event in events {
+            assert_eq!(block_on(subscriber.recv()), Some(event));
+        }
+        assert_eq!(subscriber.try_recv(), None);
+
+        // a late subscriber only hears of what comes after it
+        let mut late = queue.subscribe();
+        queue.clear();
+        assert_eq!(late.try_recv(), Some(QueueEvent::Removed));
+        assert_eq!(late.try_recv(), None);
+    }
+
+    #[test]
+    fn events_from_many_threads_arrive_in_the_order_of_the_changes() {
+        for _ in 0..200 {
+            let queue: SharedQueue<u32, Vec<u32>> = SharedQueue::default();
+            let mut subscriber = queue.subscribe();
+            let start = Arc::new(Barrier::new(2));
+            let writers: Vec<_> = (0..2)
+                .map(|writer| {
+                    let (queue, start) = (queue.clone(), Arc::clone(&start));
+                    thread::spawn(move || {
+                        start.wait();
+                        for value in 0..15 {
+                            queue.add_item(value, true).unwrap();
+                            if writer == 1 {
+                                queue.clear();
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+
+            // replaying the events has to end up with as many items as the queue has
+            let mut len = 0;
+            while let Some(event) = subscriber.try_recv() {
+                match event {
+                    QueueEvent::Added => len += 1,
+                    QueueEvent::Removed => len = 0,
+                    event => panic!("unexpected {event:?}"),
+                }
+            }
+            assert_eq!(len, queue.remaining_count());
+        }
+    }
+
+    #[test]
+    fn subscribers_can_read_the_queue_when_woken() {
+        let queue: SharedQueue<u32, Vec<u32>> = SharedQueue::default();
+        let changed = queue.changed();
+        let reader = queue.clone();
+        let handle = thread::spawn(move || {
+            let event = block_on(changed);
+            (event, reader.current_cloned().ok().map(|item| item.item))
+        });
+        queue.add_item(7, true).unwrap();
+        assert_eq!(
+            handle.join().unwrap(),
+            (Some(QueueEvent::Added), Some(QueueItemType::Single(7))),
+        );
+    }
+
+    #[test]
+    fn slow_subscribers_miss_nothing_but_detail() {
+        let queue: SharedQueue<u32, Vec<u32>> = SharedQueue::default();
+        let mut subscriber = queue.subscribe();
+        for value in 0..100 {
+            queue.add_item(value, true).unwrap();
+        }
+        let mut events = vec![];
+        while let Some(event) = subscriber.try_recv() {
+            events.push(event);
+        }
+        assert_eq!(events[0], QueueEvent::Changed);
+        assert!(events[1..].iter().all(|&event| event == QueueEvent::Added));
+        assert!(events.len() < 100);
+    }
+
+    #[test]
+    fn subscribers_end_with_the_queue() {
+        let queue: SharedQueue<u32, Vec<u32>> = SharedQueue::default();
+        let mut subscriber = queue.subscribe();
+        queue.add_item(0, true).unwrap();
+        drop(queue);
+        assert_eq!(block_on(subscriber.recv()), Some(QueueEvent::Added));
+        assert_eq!(block_on(subscriber.recv()), None);
+    }
+}
+
+/// Where the AddHere marker is, checking there is only one
+fn addhere(queue: &TestQueue) -> Option<usize> {
+    let marked = queue.items.iter().filter(|item| item.state == QueueState::AddHere).count();
+    assert!(marked <= 1, "{marked} AddHere items");
+    queue.items.iter().position(|item| item.state == QueueState::AddHere)
+}
+
+#[test]
+fn add_item_next_keeps_one_addhere() {
+    // an empty queue
+    let mut empty = TestQueue::default();
+    empty.add_item_next(10).unwrap();
+    assert_eq!(states(&empty), vec![QueueState::AddHere]);
+    empty.add_item_next(11).unwrap();
+    assert_eq!(values(&empty.items), vec![10, 11]);
+    assert_eq!(states(&empty), vec![QueueState::NoState, QueueState::AddHere]);
+
+    // only the current item, which has the marker
+    let mut single = queue(1);
+    assert_eq!(addhere(&single), Some(0));
+    single.add_item_next(10).unwrap();
+    assert_eq!(addhere(&single), Some(1));
+    single.add_item(20, false).unwrap();
+    assert_eq!(values(&single.items), vec![0, 10, 20]);
+    assert_eq!(addhere(&single), Some(2));
+
+    // the current item has the marker and others come after it
+    let items = (0..3).map(QueueItemType::Single).collect();
+    let mut marked: TestQueue = QueueBuilder::new().items(items).addhere(0).build();
+    marked.add_item_next(10).unwrap();
+    assert_eq!(values(&marked.items), vec![0, 10, 1, 2]);
+    assert_eq!(addhere(&marked), Some(1));
+    marked.add_item(20, false).unwrap();
+    assert_eq!(values(&marked.items), vec![0, 10, 20, 1, 2]);
+    assert_eq!(addhere(&marked), Some(2));
+
+    // an upcoming item deep in the queue keeps the marker
+    let mut deep = queue(4);
+    deep.add_item_next(10).unwrap();
+    deep.add_item_next(11).unwrap();
+    assert_eq!(values(&deep.items), vec![0, 11, 10, 1, 2, 3]);
+    assert_eq!(addhere(&deep), Some(5));
+    deep.add_item(20, false).unwrap();
+    assert_eq!(values(&deep.items), vec![0, 11, 10, 1, 2, 3, 20]);
+    assert_eq!(addhere(&deep), Some(6));
+}
+
+#[test]
+fn add_multi_next_follows_the_input_order() {
+    let batch: Vec<_> = [10, 11, 12].map(QueueItemType::Single).into();
+    for len in [0, 1, 8] {
+        let mut queue = queue(len);
+        queue.add_multi_next(batch.clone()).unwrap();
+
+        // right after the current item, if there is one
+        let at = len.min(1) as usize;
+        let mut expected: Vec<_> = (0..len).collect();
+        expected.splice(at..at, [10, 11, 12]);
+        assert_eq!(values(&queue.items), expected, "len = {len}");
+        // the last of them only takes the marker if no upcoming item has it
+        let marker = if len > 1 { expected.len() - 1 } else { at + 2 };
+        assert_eq!(addhere(&queue), Some(marker), "len = {len}");
+    }
+
+    let mut queue = TestQueue::default();
+    queue.add_multi_next(vec![]).unwrap();
+    assert!(queue.items.is_empty());
+}
+
+#[test]
+fn add_multi_next_plays_the_batch_in_order() {
+    let mut queue = queue(3);
+    queue.next().unwrap();
+    let items = vec![QueueItemType::Single(10), QueueItemType::Multi(vec![20, 21])];
+    queue.add_multi_next(items).unwrap();
+    queue.add_multi_next(vec![QueueItemType::Single(30), QueueItemType::Single(31)]).unwrap();
+
+    let mut played = Vec::new();
+    while let Ok(item) = queue.next() {
+        played.push(match &item.item {
+            QueueItemType::Single(value) => *value,
+            QueueItemType::Multi(tracks) => tracks[item.track],
+        });
+    }
+    // each batch in the order it was given, the later one first
+    assert_eq!(played, vec![30, 31, 10, 20, 21, 2]);
+}
+
+#[test]
+fn extend_matches_add_multi() {
+    for by_human in [false, true] {
+        for start in [TestQueue::default(), busy_queue()] {
+            let mut extended = copy(&start);
+            extended.extend_singles([1, 2, 3], by_human).unwrap();
+            extended.extend_groups([vec![4, 5], vec![6]], by_human).unwrap();
+
+            let mut added = copy(&start);
+            added.add_multi((1..=3).map(QueueItemType::Single).collect(), by_human).unwrap();
+            let groups = vec![QueueItemType::Multi(vec![4, 5]), QueueItemType::Multi(vec![6])];
+            added.add_multi(groups, by_human).unwrap();
+            assert_eq!(extended.checkpoint(), added.checkpoint());
+        }
+    }
+
+    let mut added = TestQueue::default();
+    added.add_multi((0..4).map(QueueItemType::Single).collect(), false).unwrap();
+    // a new queue seeds its own shuffle generator, so only the items can match
+    assert_eq!(TestQueue::from(vec![0, 1, 2, 3]).items, added.items);
+}
+
+#[test]
+#[ignore = "run by move_to_writes_nothing_to_stderr"]
+fn move_to_around_the_queue() {
+    let mut queue = queue(5);
+    queue.move_to(3).unwrap();
+    assert_eq!(values(&queue.items), vec![3, 4]);
+    queue.move_to(0).unwrap();
+    assert!(queue.move_to(2).is_err());
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn move_to_writes_nothing_to_stderr() {
+    extern crate std;
+    use std::process::Command;
+
+    let test = "tests::move_to_around_the_queue";
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--ignored", "--nocapture"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(std::str::from_utf8(&output.stdout).unwrap().contains("1 passed"));
+    assert_eq!(std::str::from_utf8(&output.stderr).unwrap(), "");
+}
+
+#[test]
+fn set_items_normalizes_states() {
+    use QueueState::*;
+
+    // stray markers from wherever the items came from
+    let tracks: Vec<_> = [AddHere, Played, First, NoState]
+        .into_iter()
+        .zip(0..)
+        .map(|(state, value)| {
+            QueueItem::from_item_type(QueueItemType::Single(value), true).with_state(state)
+        })
+        .collect();
+
+    let mut queue = busy_queue();
+    let played = queue.played.clone();
+    queue.set_items(tracks);
+    assert_eq!(states(&queue), vec![First, NoState, NoState, AddHere]);
+    assert!(!queue.is_shuffled());
+    assert_eq!(queue.played, played);
+    queue.add_item(4, false).unwrap();
+    assert_eq!(values(&queue.items), vec![0, 1, 2, 3, 4]);
+    assert_eq!(addhere(&queue), Some(4));
+
+    queue.set_items(vec![]);
+    assert!(queue.items.is_empty());
+    queue.add_item(5, false).unwrap();
+    queue.add_item(6, false).unwrap();
+    assert_eq!(values(&queue.items), vec![5, 6]);
+    assert_eq!(addhere(&queue), Some(1));
+}
+
+#[test]
+fn sorts_and_reverses_can_keep_the_current_item() {
+    let unsorted = || {
+        let mut queue = TestQueue::default();
+        queue.extend_singles([5, 3, 8, 1, 9, 2], false).unwrap();
+        queue
+    };
+    let value = |item: &QueueItemType<u32, Vec<u32>>| match item {
+        QueueItemType::Single(value) => *value,
+        QueueItemType::Multi(_) => 0,
+    };
+
+    for (keep_current, sorted, reversed) in [
+        (true, vec![5, 1, 2, 3, 8, 9], vec![5, 2, 9, 1, 8, 3]),
+        (false, vec![1, 2, 3, 5, 8, 9], vec![2, 9, 1, 8, 3, 5]),
+    ] {
+        let mut queue = unsorted();
+        queue.sort_upcoming_by(keep_current, value);
+        assert_eq!(values(&queue.items), sorted);
+        assert!(queue.validate().is_ok());
+
+        let mut queue = unsorted();
+        let mut keys = 0;
+        queue.sort_upcoming_by_cached_key(keep_current, |item| {
+            keys += 1;
+            value(item)
+        });
+        assert_eq!(values(&queue.items), sorted);
+        assert_eq!(keys, if keep_current { 5 } else { 6 });
+        assert!(queue.validate().is_ok());
+
+        let mut queue = unsorted();
+        queue.reverse_upcoming(keep_current);
+        assert_eq!(values(&queue.items), reversed);
+        assert!(queue.validate().is_ok());
+    }
+
+    // a shuffled queue with groups and priorities in it keeps its current item too
+    let mut queue = busy_queue();
+    let current = queue.items[0].item.clone();
+    queue.sort_upcoming_by(true, |item| core::cmp::Reverse(item.track_count()));
+    queue.reverse_upcoming(true);
+    queue.sort_by_priority();
+    assert_eq!(queue.items[0].item, current);
+    assert!(queue.validate().is_ok());
+}
+
+#[test]
+fn sort_by_priority_is_stable_and_leaves_addhere_in_place() {
+    let mut queue = TestQueue::default();
+    for (value, priority) in [(0, 9), (1, 0), (2, 2), (3, 0), (4, 2), (5, -1)] {
+        queue.add_item_with_priority(value, false, priority).unwrap();
+    }
+    let marker = addhere(&queue);
+
+    queue.sort_by_priority();
+    assert_eq!(values(&queue.items), vec![0, 2, 4, 1, 3, 5]);
+    assert_eq!(addhere(&queue), marker);
+    assert!(queue.validate().is_ok());
+}
+
+#[test]
+fn builder_options_match_the_setters() {
+    type Setting = (QueueBuilder<u32, Vec<u32>>, &'static dyn Fn(&mut TestQueue));
+
+    let options: [Setting; 8] = [
+        (QueueBuilder::new(), &|_| ()),
+        (QueueBuilder::new().max_len(3), &|q| q.set_max_len(Some(3))),
+        (
+            QueueBuilder::new().eviction_policy(EvictionPolicy::DropOldestAuto),
+            &|q| q.set_eviction_policy(EvictionPolicy::DropOldestAuto),
+        ),
+        (QueueBuilder::new().loop_(true), &|q| q.enable_loop()),
+        (
+            QueueBuilder::new().loop_setting(LoopSetting::Times(2)),
+            &|q| q.set_loop(LoopSetting::Times(2)),
+        ),
+        (QueueBuilder::new().history_limit(2), &|q| q.set_history_limit(Some(2))),
+        (QueueBuilder::new().history_dedup(true), &|q| q.set_history_dedup(true)),
+        (QueueBuilder::new().repeat_one(true), &|q| q.set_repeat_one(true)),
+    ];
+    for (builder, set) in options {
+        // every case is seeded, a new queue seeds its own shuffle generator otherwise
+        let built = builder.seed(9).build();
+        let mut expected = TestQueue::default();
+        expected.seed_shuffle(9);
+        set(&mut expected);
+        assert_eq!(built.checkpoint(), expected.checkpoint());
+        assert!(built.validate().is_ok());
+    }
+}
+
+#[test]
+fn next_and_prev_give_the_marker_back() {
+    // the marker on the last item, on the current one, and on an item in between
+    let builders = [
+        QueueBuilder::new(),
+        QueueBuilder::new().addhere(0),
+        QueueBuilder::new().addhere(1),
+    ];
+    for builder in builders {
+        let items: Vec<_> = (0..4).map(QueueItemType::Single).collect();
+        let mut untouched: TestQueue = builder.items(items).build();
+        let mut navigated = copy(&untouched);
+        navigated.next().unwrap();
+        navigated.next().unwrap();
+        navigated.prev().unwrap();
+        navigated.prev().unwrap();
+        assert_eq!(values(&navigated.items), values(&untouched.items));
+        assert_eq!(addhere(&navigated), addhere(&untouched));
+
+        navigated.add_item(10, false).unwrap();
+        untouched.add_item(10, false).unwrap();
+        assert_eq!(values(&navigated.items), values(&untouched.items));
+        assert_eq!(addhere(&navigated), addhere(&untouched));
+    }
+}
+
+#[test]
+fn clear_except_keeps_one_of_equal_items() {
+    let mut queue = queue(3);
+    queue.extend_singles([1, 1], false).unwrap();
+    queue.enable_shuffle();
+    let index = queue.position_of(&QueueItemType::Single(1)).unwrap();
+    assert_eq!(queue.clear_except(index).ok(), Some(()));
+    assert_eq!(values(&queue.items), vec![1]);
+    assert_eq!(states(&queue), vec![QueueState::AddHere]);
+    assert_eq!(queue.play_order(), Some(&[0][..]));
+
+    assert!(queue.clear_except(1).is_err());
+    assert!(TestQueue::default().clear_except(0).is_err());
+}
+
+/// Runs `steps` edits picked from [`EDITS`] by `seed`, calling `check` with the queue as it was
+/// and is after each one
+fn random_edits(seed: u64, steps: usize, mut check: impl FnMut(&str, &TestQueue, &TestQueue)) {
+    let mut rng = rng::Rng::new(seed);
+    let mut queue = busy_queue();
+    for _ in 0..steps {
+        let (name, edit) = EDITS[rng.below(EDITS.len())];
+        let before = copy(&queue);
+        edit(&mut queue);
+        check(name, &before, &queue);
+    }
+}
+
+#[test]
+fn applying_the_diff_gives_the_current_items() {
+    for seed in 0..200 {
+        random_edits(seed, 30, |name, before, after| {
+            let mut items = before.items.clone();
+            for delta in after.diff(&before.items) {
+                match delta {
+                    QueueDelta::Inserted { index } => {
+                        items.insert(index, after.items[index].clone());
+                    }
+                    QueueDelta::Removed { index } => {
+                        items.remove(index);
+                    }
+                    QueueDelta::Moved { from, to } => {
+                        let item = items.remove(from);
+                        items.insert(to, item);
+                    }
+                    QueueDelta::StateChanged { index } => items[index] = after.items[index].clone(),
+                }
+            }
+            assert_eq!(items, after.items, "seed {seed}, {name}");
+        });
+    }
+}
+
+fn random_command(rng: &mut rng::Rng) -> QueueCommand<u32, Vec<u32>> {
+    use QueueCommand::*;
+
+    let index = rng.below(8);
+    let value = rng.below(5) as u32;
+    let by_human = rng.below(2) == 0;
+    let item = if rng.below(4) == 0 {
+        QueueItemType::Multi(vec![value, value + 1])
+    } else {
+        QueueItemType::Single(value)
+    };
+    let position = [QueuePosition::AtAddHere, QueuePosition::End, QueuePosition::Index(index)];
+    let setting = [LoopSetting::Off, LoopSetting::Forever, LoopSetting::Times(1)];
+    match rng.below(22) {
+        0 => Add { item: value, by_human },
+        1 => AddNext(value),
+        2 => AddMulti { items: vec![item.clone(), item], by_human },
+        3 => AddMultiNext(vec![item]),
+        4 => AddAt { item, position: position[rng.below(3)], by_human },
+        5 => Insert { index, item, by_human, addhere: rng.below(2) == 0 },
+        6 => Remove(index),
+        7 => Move { from: index, to: rng.below(8) },
+        8 => Swap(index, rng.below(8)),
+        9 | 10 => Next,
+        11 => Skip,
+        12 => Prev,
+        13 => MoveTo(index),
+        14 => Clear,
+        15 => ClearUpcoming,
+        16 => ClearPlayed,
+        17 => ClearAll,
+        18 => EnableShuffle,
+        19 => [DisableShuffle, Unshuffle][rng.below(2)].clone(),
+        20 => SetLoop(setting[rng.below(3)]),
+        _ => SetRepeatOne(rng.below(3) == 0),
+    }
+}
+
+/// What [`Queue::apply`] should do, written out with the methods themselves
+fn call(queue: &mut TestQueue, command: QueueCommand<u32, Vec<u32>>) -> Result<(), QueueError> {
+    use QueueCommand::*;
+
+    let len = queue.items.len();
+    match command {
+        Add { item, by_human } => queue.add_item(item, by_human)?,
+        AddNext(item) => queue.add_item_next(item)?,
+        AddMulti { items, by_human } => queue.add_multi(items, by_human)?,
+        AddMultiNext(items) => queue.add_multi_next(items)?,
+        AddAt { item, position, by_human } => queue.add_at(item, position, by_human)?,
+        Insert { index, item, by_human, addhere } => queue.insert(index, item, by_human, addhere)?,
+        Remove(index) => drop(queue.remove_item(index)?),
+        Move { from, to } if from < len && to < len => queue.move_item(from, to),
+        Swap(a, b) if a < len && b < len => queue.swap(a, b),
+        Move { from: index, to } | Swap(index, to) => {
+            return Err(QueueError::OutOfBounds { index: index.max(to), len });
+        }
+        Next => drop(queue.next()?),
+        Skip => drop(queue.skip()?),
+        Prev => drop(queue.prev()?),
+        MoveTo(index) => queue.move_to(index)?,
+        Clear => queue.clear(),
+        ClearUpcoming => drop(queue.clear_upcoming()),
+        ClearPlayed => queue.clear_played(),
+        ClearAll => queue.clear_all(),
+        EnableShuffle => queue.enable_shuffle(),
+        DisableShuffle => queue.disable_shuffle(),
+        Unshuffle => queue.unshuffle(),
+        SetLoop(setting) => queue.set_loop(setting),
+        SetRepeatOne(repeat) => queue.set_repeat_one(repeat),
+    }
+    Ok(())
+}
+
+/// A checkpoint without the times items were played, which two queues only share by chance
+fn timeless(queue: &TestQueue) -> QueueSnapshot<u32, Vec<u32>> {
+    #[cfg_attr(not(feature = "std"), allow(unused_mut))]
+    let mut snapshot = queue.checkpoint();
+    #[cfg(feature = "std")]
+    for item in &mut snapshot.played {
+        item.played_at = None;
+    }
+    snapshot
+}
+
+#[test]
+fn apply_matches_calling_the_methods() {
+    for seed in 0..200 {
+        let mut rng = rng::Rng::new(seed);
+        let mut applied = busy_queue();
+        let mut called = copy(&applied);
+        let mut replica = copy(&applied);
+        for step in 0..60 {
+            let command = random_command(&mut rng);
+            replica.apply(command.clone()).ok();
+            let expected = format!("{:?}", call(&mut called, command.clone()));
+            let result = format!("{:?}", applied.apply(command.clone()));
+            assert_eq!(result, expected, "seed {seed}, {command:?}");
+            assert_eq!(timeless(&applied), timeless(&called), "seed {seed}, step {step}");
+            assert_eq!(timeless(&applied), timeless(&replica), "seed {seed}, step {step}");
+        }
+    }
+}
+
+#[test]
+fn each_call_bumps_the_generation_once() {
+    for (name, edit) in EDITS.iter().filter(|(name, _)| *name != "play through") {
+        let mut queue = busy_queue();
+        let generation = queue.generation();
+        edit(&mut queue);
+        assert_eq!(queue.generation(), generation + 1, "{name}");
+    }
+
+    // a rollback changes the queue back, the rest of a transaction leaves it alone
+    let mut queue = busy_queue();
+    let generation = queue.generation();
+    queue.begin_transaction();
+    queue.begin_transaction();
+    queue.commit().unwrap();
+    assert_eq!(queue.generation(), generation);
+    queue.rollback().unwrap();
+    assert_eq!(queue.generation(), generation + 1);
+
+    // failing calls count too, they can't always tell up front
+    let mut queue = busy_queue();
+    let generation = queue.generation();
+    assert!(queue.move_to(99).is_err());
+    assert_eq!(queue.generation(), generation + 1);
+
+    let queue = busy_queue();
+    let generation = queue.generation();
+    let previous = queue.items.clone();
+    queue.current().unwrap();
+    queue.next_n(5);
+    queue.peek_many(3);
+    queue.iter_play_order().count();
+    queue.play_position_of_index(2);
+    queue.display_index_of_play_position(2);
+    queue.diff(&previous);
+    queue.diff_summary(&queue);
+    queue.position_of(&QueueItemType::Single(4));
+    queue.current_track_in_group();
+    queue.checkpoint();
+    queue.export_with(true, |item| format!("{:?}", item.item));
+    queue.validate().unwrap();
+    assert_eq!(queue.generation(), generation);
+}
+
+#[test]
+fn play_order_mappings_stay_inverse() {
+    for seed in 0..200 {
+        random_edits(seed, 30, |name, _, queue| {
+            let len = queue.items.len();
+            if let Some(order) = queue.play_order() {
+                let mut sorted = order.to_vec();
+                sorted.sort_unstable();
+                assert_eq!(sorted, (0..len).collect::<Vec<_>>(), "seed {seed}, {name}");
+            }
+            for n in 0..len {
+                let index = queue.display_index_of_play_position(n).unwrap();
+                assert_eq!(queue.play_position_of_index(index), Some(n), "seed {seed}, {name}");
+            }
+            assert_eq!(queue.display_index_of_play_position(len), None);
+            assert_eq!(queue.play_position_of_index(len), None);
+            assert!(queue.iter_play_order().eq(&queue.items));
+        });
+    }
+}
+
+#[test]
+fn move_up_and_down_one_place() {
+    let mut queue = queue(4);
+    assert_eq!(queue.move_up(3, false).ok(), Some(2));
+    assert_eq!(values(&queue.items), vec![0, 1, 3, 2]);
+    // the item takes its marker along
+    assert_eq!(addhere(&queue), Some(2));
+    assert_eq!(queue.move_down(2, false).ok(), Some(3));
+    assert_eq!(values(&queue.items), vec![0, 1, 2, 3]);
+    assert_eq!(addhere(&queue), Some(3));
+
+    // the ends and the current item stay put unless asked
+    assert_eq!(queue.move_up(0, true).ok(), Some(0));
+    assert_eq!(queue.move_down(3, true).ok(), Some(3));
+    assert_eq!(queue.move_up(1, false).ok(), Some(1));
+    assert_eq!(queue.move_down(0, false).ok(), Some(0));
+    assert_eq!(values(&queue.items), vec![0, 1, 2, 3]);
+    assert_eq!(queue.move_up(1, true).ok(), Some(0));
+    assert_eq!(queue.move_down(0, true).ok(), Some(1));
+    assert_eq!(values(&queue.items), vec![0, 1, 2, 3]);
+
+    assert!(queue.move_up(4, true).is_err());
+    assert!(queue.move_down(4, true).is_err());
+    assert!(TestQueue::default().move_up(0, true).is_err());
+
+    // on a shuffled queue, moving each item up and back down changes nothing in either order
+    let mut shuffled = busy_queue();
+    let display = |queue: &TestQueue| -> Vec<QueueItem<u32, Vec<u32>>> {
+        (0..queue.items.len())
+            .map(|index| queue.items[queue.play_position_of_index(index).unwrap()].clone())
+            .collect()
+    };
+    let before = display(&shuffled);
+    for index in 0..shuffled.items.len() {
+        let moved = shuffled.move_up(index, true).unwrap();
+        shuffled.move_down(moved, true).unwrap();
+        assert!(shuffled.validate().is_ok());
+    }
+    assert_eq!(display(&shuffled), before);
+}
+
+#[test]
+fn filter_map_passes_markers_on_without_clobbering() {
+    use QueueState::*;
+
+    let items = (0..2).map(QueueItemType::Single).collect();
+    let mut queue: TestQueue = QueueBuilder::new().items(items).build();
+    assert_eq!(states(&queue), vec![First, AddHere]);
+    queue.filter_map_items(|item| (item != QueueItemType::Single(0)).then_some(item));
+    assert_eq!(states(&queue), vec![AddHere]);
+
+    let items = (0..4).map(QueueItemType::Single).collect();
+    let mut queue: TestQueue = QueueBuilder::new().items(items).build();
+    queue.filter_map_items(|item| (item != QueueItemType::Single(0)).then_some(item));
+    assert_eq!(states(&queue), vec![First, NoState, AddHere]);
+    queue.filter_map_items(|item| (item != QueueItemType::Single(3)).then_some(item));
+    assert_eq!(values(&queue.items), vec![1, 2]);
+    assert_eq!(states(&queue), vec![First, AddHere]);
+
+    // the item after a removed AddHere item takes the marker
+    let items = (0..4).map(QueueItemType::Single).collect();
+    let mut queue: TestQueue = QueueBuilder::new().items(items).addhere(1).build();
+    queue.filter_map_items(|item| (item != QueueItemType::Single(1)).then_some(item));
+    assert_eq!(states(&queue), vec![First, AddHere, NoState]);
+    queue.add_item(9, false).unwrap();
+    assert_eq!(values(&queue.items), vec![0, 2, 9, 3]);
+}
+
+#[test]
+fn unshuffle_keeps_unplayed_items_upcoming() {
+    for seed in 0..20 {
+        let mut queue = queue(5);
+        queue.seed_shuffle(seed);
+        queue.enable_shuffle();
+        queue.move_item(4, 1);
+        queue.next().unwrap();
+        let played = queue.played.clone();
+        let current = values(&queue.items)[0];
+        let was_played = values(&played);
+
+        queue.unshuffle();
+        assert_eq!(queue.played, played, "seed {seed}");
+        let mut expected = vec![current];
+        expected.extend((0..5).filter(|&value| value != current && !was_played.contains(&value)));
+        assert_eq!(values(&queue.items), expected, "seed {seed}");
+        assert!(addhere(&queue).is_some(), "seed {seed}");
+        assert!(queue.validate().is_ok());
+    }
+}
+
+#[test]
+fn move_to_goes_to_the_index_among_equal_items() {
+    let mut queue = TestQueue::from(vec![7, 8, 7, 9]);
+    queue.move_to(2).unwrap();
+    assert_eq!(values(&queue.items), vec![7, 9]);
+    assert_eq!(values(&queue.played), vec![7, 8]);
+    assert_eq!(addhere(&queue), Some(1));
+}
@@ -0,0 +1,224 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::mem;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+use crate::rng::Rng;
+use crate::{
+    EvictionPolicy, ItemMeta, ItemTag, LoopSetting, Queue, QueueError, QueueItem, QueueItemType,
+    QueueState, QueueStats, ShuffleMode,
+};
+
+/// The queue as it was when a transaction began, with the values of the items left out.
+///
+/// Values stay in the queue, or in `buried` once they leave it, and a rollback finds them again
+/// by the key each item was given
+#[derive(Debug)]
+pub(crate) struct Savepoint {
+    items: Vec<ItemLayout>,
+    played: Vec<ItemLayout>,
+    loop_: LoopSetting,
+    loop_times: u32,
+    repeat_one: bool,
+    shuffle: Option<Vec<usize>>,
+    shuffle_mode: ShuffleMode,
+    rng: Rng,
+    history_limit: Option<usize>,
+    history_dedup: bool,
+    stats: QueueStats,
+    addhere_hint: Option<usize>,
+    addhere_passed: usize,
+    max_len: Option<usize>,
+    eviction_policy: EvictionPolicy,
+}
+
+/// Everything about an item but its value
+#[derive(Debug)]
+struct ItemLayout {
+    state: QueueState,
+    by_human: bool,
+    track: usize,
+    meta: ItemMeta,
+    errored: bool,
+    priority: i32,
+    #[cfg(feature = "std")]
+    played_at: Option<SystemTime>,
+    tag: ItemTag,
+}
+
+impl ItemLayout {
+    fn of<T, U>(item: &QueueItem<T, U>) -> Self {
+        ItemLayout {
+            state: item.state,
+            by_human: item.by_human,
+            track: item.track,
+            meta: item.meta.clone(),
+            errored: item.errored,
+            priority: item.priority,
+            #[cfg(feature = "std")]
+            played_at: item.played_at,
+            tag: item.tag,
+        }
+    }
+
+    fn with_value<T, U>(self, item: QueueItemType<T, U>) -> QueueItem<T, U> {
+        QueueItem {
+            item,
+            state: self.state,
+            by_human: self.by_human,
+            track: self.track,
+            meta: self.meta,
+            errored: self.errored,
+            priority: self.priority,
+            #[cfg(feature = "std")]
+            played_at: self.played_at,
+            tag: self.tag,
+        }
+    }
+}
+
+impl<T, U> Queue<T, U> {
+    /// Keeps the value of an item leaving the queue, in case a transaction is rolled back
+    pub(crate) fn bury(&mut self, item: QueueItem<T, U>) {
+        if !self.savepoints.is_empty() && item.tag.key != 0 {
+            self.buried.push((item.tag.key, item.item));
+        }
+    }
+
+    /// [`Queue::bury`] for every item in `items`
+    pub(crate) fn bury_all<I: IntoIterator<Item = QueueItem<T, U>>>(&mut self, items: I) {
+        for item in items {
+            self.bury(item);
+        }
+    }
+}
+
+impl<
+    T: Debug + Clone + PartialEq,
+    U: Debug + PartialEq + Clone + IntoIterator,
+> Queue<T, U> {
+    /// Starts a transaction, which [`Queue::rollback`] undoes and [`Queue::commit`] keeps.
+    ///
+    /// Everything done until then is undone by a rollback, moving with `next` and `prev`
+    /// included. Transactions can be nested, each commit or rollback ending the innermost one.
+    ///
+    /// Nothing is copied up front, only the items that are dropped or handed back while it is
+    /// open. Because of that, items changed or removed through `items` and `played` directly
+    /// can't be put back, while ones pushed there are removed by a rollback like any other new
+    /// item
+    pub fn begin_transaction(&mut self) {
+        // keys only have to tell values apart within the outermost transaction
+        let outermost = self.savepoints.is_empty();
+        if outermost {
+            self.next_key = 0;
+        }
+        for item in self.items.iter_mut().chain(&mut self.played) {
+            if outermost || item.tag.key == 0 {
+                self.next_key += 1;
+                item.tag.key = self.next_key;
+            }
+        }
+
+        self.savepoints.push(Savepoint {
+            items: self.items.iter().map(ItemLayout::of).collect(),
+            played: self.played.iter().map(ItemLayout::of).collect(),
+            loop_: self.loop_,
+            loop_times: self.loop_times,
+            repeat_one: self.repeat_one,
+            shuffle: self.shuffle.clone(),
+            shuffle_mode: self.shuffle_mode,
+            rng: self.rng.clone(),
+            history_limit: self.history_limit,
+            history_dedup: self.history_dedup,
+            stats: self.stats,
+            addhere_hint: self.addhere_hint,
+            addhere_passed: self.addhere_passed,
+            max_len: self.max_len,
+            eviction_policy: self.eviction_policy,
+        });
+    }
+
+    /// Keeps the changes made since the innermost transaction began
+    pub fn commit(&mut self) -> Result<(), QueueError> {
+        self.savepoints.pop().ok_or(QueueError::NoTransaction)?;
+        if self.savepoints.is_empty() {
+            self.buried.clear();
+        }
+        Ok(())
+    }
+
+    /// Puts the queue back the way it was when the innermost transaction began
+    pub fn rollback(&mut self) -> Result<(), QueueError> {
+        let Some(savepoint) = self.savepoints.pop() else {
+            return Err(QueueError::NoTransaction);
+        };
+        self.generation += 1;
+
+        // every item with the same key has the same value, so any of them will do
+        let mut values: BTreeMap<u64, Vec<QueueItemType<T, U>>> = BTreeMap::new();
+        let queued = mem::take(&mut self.items).into_iter().chain(mem::take(&mut self.played));
+        let queued = queued.map(|item| (item.tag.key, item.item));
+        for (key, value) in mem::take(&mut self.buried).into_iter().chain(queued) {
+            values.entry(key).or_default().push(value);
+        }
+        let mut rebuild = |layouts: Vec<ItemLayout>| -> Vec<QueueItem<T, U>> {
+            layouts
+                .into_iter()
+                .filter_map(|layout| {
+                    let value = values.get_mut(&layout.tag.key)?.pop()?;
+                    Some(layout.with_value(value))
+                })
+                .collect()
+        };
+        self.items = rebuild(savepoint.items);
+        self.played = rebuild(savepoint.played);
+
+        // an outer transaction can still need what is left, apart from items new to all of them
+        if !self.savepoints.is_empty() {
+            self.buried = values
+                .into_iter()
+                .filter(|&(key, _)| key != 0)
+                .flat_map(|(key, values)| values.into_iter().map(move |value| (key, value)))
+                .collect();
+        }
+
+        self.loop_ = savepoint.loop_;
+        self.loop_times = savepoint.loop_times;
+        self.repeat_one = savepoint.repeat_one;
+        self.shuffle = savepoint.shuffle;
+        self.shuffle_mode = savepoint.shuffle_mode;
+        self.rng = savepoint.rng;
+        self.history_limit = savepoint.history_limit;
+        self.history_dedup = savepoint.history_dedup;
+        self.stats = savepoint.stats;
+        self.addhere_hint = savepoint.addhere_hint;
+        self.addhere_passed = savepoint.addhere_passed;
+        self.max_len = savepoint.max_len;
+        self.eviction_policy = savepoint.eviction_policy;
+        debug_assert!(self.validate().is_ok());
+        Ok(())
+    }
+
+    /// How many transactions are open
+    pub fn transaction_depth(&self) -> usize {
+        self.savepoints.len()
+    }
+
+    /// Keeps a copy of the value of an item that is handed out or about to be changed
+    pub(crate) fn bury_copy(&mut self, item: &QueueItem<T, U>) {
+        if !self.savepoints.is_empty() && item.tag.key != 0 {
+            self.buried.push((item.tag.key, item.item.clone()));
+        }
+    }
+
+    /// [`Queue::bury_copy`] for every item in `played`, which is about to be handed out
+    pub(crate) fn bury_played_copies(&mut self) {
+        let played = mem::take(&mut self.played);
+        for item in &played {
+            self.bury_copy(item);
+        }
+        self.played = played;
+    }
+}